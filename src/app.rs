@@ -1,17 +1,31 @@
 use std::borrow::Borrow;
 use std::fs::File;
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
 use std::marker::PhantomData;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use clap;
 use serde_json;
 use shlex;
 use url::Url;
 
-use config;
-use language;
-use wandbox::{self, Wandbox};
+use backend::{self, Backend};
+use cassette::{self, Cassette};
+use config::{self, CompilerAlias, Config, ConfigFile, Entry, LoadOptions};
+use directive::Directives;
+use language::{Language, Languages};
+use local::Local;
+use logger;
+use manifest::{self, Manifest};
+use mock::{self, MockServer};
+use options::{self, OptionSet, Shortcut};
+use preset::{self, Presets};
+use proxy::{Proxy, ProxyOptions};
+use server::Server;
+use util;
+use wandbox::{self, CompilerInfo, Wandbox};
+use ResultExt;
 
 pub struct ListApp<'a> {
   dump: bool,
@@ -38,9 +52,8 @@ impl<'a, 'b: 'a> From<&'b clap::ArgMatches<'a>> for ListApp<'a> {
 }
 
 impl<'a> ListApp<'a> {
-  fn run(self) -> Result<i32, ::Error> {
-    let config = config::Config::load()?;
-    let cli = Wandbox::new(config.url);
+  fn run(self, config: &Config) -> Result<i32, ::Error> {
+    let cli = backend::from_config(config)?;
 
     if self.dump {
       let mut res = cli.get_compiler_info_raw()?;
@@ -54,7 +67,7 @@ impl<'a> ListApp<'a> {
 
       let mut langs = BTreeMap::new();
       for compiler in &info {
-        let compiler: &CompilerInfo = &compiler;
+        let compiler: &CompilerInfo = compiler;
         let language = compiler.language.clone();
         if !langs.contains_key(&language) {
           langs.insert(language.clone(), Vec::new());
@@ -66,7 +79,7 @@ impl<'a> ListApp<'a> {
         println!("[{}]", lang);
         for compiler in compilers {
           println!("- {}", compiler.name);
-          if self.show_switches && compiler.switches.len() > 0 {
+          if self.show_switches && !compiler.switches.is_empty() {
             println!("  [Switches]");
             for switch in &compiler.switches {
               match *switch {
@@ -100,17 +113,33 @@ impl<'a> ListApp<'a> {
 }
 
 
+/// Where the standard input of the program comes from.
+enum Stdin<'a> {
+  Inline(&'a str),
+  File(&'a str),
+  /// Our own standard input, given as `--stdin -`.
+  Pipe,
+  Tty,
+}
+
 pub struct CompileApp<'a> {
   filename: &'a str,
   files: Option<clap::Values<'a>>,
   compiler: Option<&'a str>,
   options: Option<&'a str>,
+  presets: Option<&'a str>,
+  shortcuts: Vec<Shortcut<'a>>,
   compiler_args: Option<&'a str>,
   runtime_args: Option<&'a str>,
-  stdin: Option<&'a str>,
+  stdin: Option<Stdin<'a>>,
   permlink: bool,
   browse: bool,
-  verbose: bool,
+  strict: bool,
+  no_validate: bool,
+  explain: bool,
+  no_directives: bool,
+  dry_run: bool,
+  emit_request: Option<&'a str>,
 }
 
 impl<'c> CompileApp<'c> {
@@ -121,125 +150,888 @@ impl<'c> CompileApp<'c> {
         [files...]                      'Supplemental files'
         --compiler=[compiler]           'Compiler name'
         --options=[options]             'Used options (separated by comma)'
+        --preset=[presets]              'Presets of options and arguments (e.g. asan,strict)'
+        --std=[std]                     'Language standard (e.g. c++17, gnu++14)'
+        --boost=[version]               'Version of Boost (e.g. 1.64, nothing)'
+        --warnings                      'Enable warnings'
+        --optimize                      'Enable optimization'
+        --sprout                        'Use Sprout'
         --compile-args=[compiler-args]  'Arguments for compiler'
         --runtime-args=[runtime-args]   'Arguments for compiled binary or interpreter'
-        --stdin=[stdin]                 'Standard input'
+        --stdin=[stdin]                 'Standard input, or `-` to pass our own standard input'
+        --stdin-file=[stdin-file]       'File passed as standard input'
+        --stdin-tty                     'Type standard input interactively until EOF'
         --permlink                      'Generate permlink and output URL at end'
         --browse                        'Open permlink URL'
-        -v, --verbose                   'Display verbose output'
+        --strict                        'Fail when raw arguments are not accepted by the compiler'
+        --no-validate                   'Send options without checking them against the compiler'
+        --explain                       'Show the effective compile command instead of sending a request'
+        --no-directives                 'Ignore directives in the source'
+        --dry-run                       'Build the request without sending it'
+        --emit-request=[path]           'Save the request as JSON (`-` for standard output)'
       "#)
+       .group(clap::ArgGroup::with_name("input").args(&["stdin", "stdin-file", "stdin-tty"]))
   }
 }
 
 impl<'a, 'b: 'a> From<&'b clap::ArgMatches<'a>> for CompileApp<'a> {
   fn from(m: &'b clap::ArgMatches<'a>) -> CompileApp<'a> {
+    let mut shortcuts = Vec::new();
+    if let Some(std) = m.value_of("std") {
+      shortcuts.push(Shortcut::Std(std));
+    }
+    if let Some(version) = m.value_of("boost") {
+      shortcuts.push(Shortcut::Boost(version));
+    }
+    if m.is_present("warnings") {
+      shortcuts.push(Shortcut::Warnings);
+    }
+    if m.is_present("optimize") {
+      shortcuts.push(Shortcut::Optimize);
+    }
+    if m.is_present("sprout") {
+      shortcuts.push(Shortcut::Sprout);
+    }
+
     CompileApp {
       filename: m.value_of("filename").unwrap(),
       files: m.values_of("files"),
       compiler: m.value_of("compiler"),
       options: m.value_of("options"),
+      presets: m.value_of("preset"),
+      shortcuts: shortcuts,
       compiler_args: m.value_of("compiler-args"),
       runtime_args: m.value_of("runtime-args"),
-      stdin: m.value_of("stdin"),
+      stdin: match (m.value_of("stdin"), m.value_of("stdin-file")) {
+        (Some("-"), _) => Some(Stdin::Pipe),
+        (Some(stdin), _) => Some(Stdin::Inline(stdin)),
+        (None, Some(path)) => Some(Stdin::File(path)),
+        (None, None) if m.is_present("stdin-tty") => Some(Stdin::Tty),
+        (None, None) => None,
+      },
       permlink: m.is_present("permlink"),
       browse: m.is_present("browse"),
-      verbose: m.is_present("verbose"),
+      strict: m.is_present("strict"),
+      no_validate: m.is_present("no-validate"),
+      explain: m.is_present("explain"),
+      no_directives: m.is_present("no-directives"),
+      dry_run: m.is_present("dry-run"),
+      emit_request: m.value_of("emit-request"),
     }
   }
 }
 
 impl<'a> CompileApp<'a> {
-  fn run(self) -> Result<i32, ::Error> {
-    let config = config::Config::load()?;
+  fn run(self, config: &Config) -> Result<i32, ::Error> {
+    let languages = Languages::with_config(&config.languages)?;
 
-    let code = self.read_code()?;
-    let compiler = self.guess_compiler().unwrap_or("gcc-head".into());
+    match self.stdin {
+      Some(Stdin::Pipe) if self.filename == "-" => {
+        bail!("the source and the standard input of the program cannot both be read from stdin; \
+               use --stdin-file for the input")
+      }
+      Some(Stdin::Tty) if self.filename == "-" => {
+        bail!("--stdin-tty cannot be used when the source is read from stdin; use --stdin-file for the input")
+      }
+      _ => (),
+    }
 
-    let mut parameter = wandbox::Parameter::new(code, compiler);
-    parameter.save_permlink(self.browse || self.permlink);
+    let code = self.read_code()?;
+    let language = self.guess_language(&languages, &code);
+    match language {
+      Some(language) => info!("language: {} (default compiler: {})", language.name, language.compiler),
+      None => info!("language: unknown"),
+    }
 
-    if let Some(options) = self.options {
-      parameter.options(options);
+    // Settings in the directives are overridden by the commandline flags.
+    let directives = if self.no_directives {
+      Directives::default()
+    } else {
+      Directives::parse(&code, language.map(|l| l.comments.as_slice()).unwrap_or(&[]))?
+    };
+    if directives != Directives::default() {
+      debug!("directives: {:?}", directives);
     }
+    let mut alias = config.expand_compiler(self.compiler
+                                             .or(directives.compiler.as_ref().map(String::as_str))
+                                             .or(language.map(|l| l.compiler.as_str()))
+                                             .unwrap_or("gcc-head"));
+    let presets = Presets::with_config(&config.presets);
+    let presets = match self.presets {
+      Some(names) => presets.resolve(names, &alias.compiler)?,
+      None => Vec::new(),
+    };
+    preset::apply(&mut alias, &presets);
+    info!("compiler: {}", alias.compiler);
+    // Explicit options override the directives, the presets and the alias in the same group of choices.
+    let preset_options = preset::options(&presets);
+    let mut options = option_layers(&alias,
+                                    &[preset_options.as_ref().map(String::as_str),
+                                      directives.options.as_ref().map(String::as_str),
+                                      self.options],
+                                    config);
+    debug!("options: {:?}", options);
+    let compiler_args = self.compiler_args.or(directives.compiler_args.as_ref().map(String::as_str));
+    let runtime_args = self.runtime_args.or(directives.runtime_args.as_ref().map(String::as_str));
+    let stdin = match self.read_stdin()? {
+      Some(stdin) => Some(stdin),
+      None => directives.stdin.clone(),
+    };
 
-    if let Some(args) = self.compiler_args.and_then(|s| shlex::split(&s)) {
+    let mut parameter = wandbox::Parameter::new(code, alias.compiler.clone());
+    parameter.save_permlink(self.browse || self.permlink);
+
+    let args = split_args(alias.compiler_args.as_ref().map(String::as_str), compiler_args)?;
+    if !args.is_empty() {
       parameter.compiler_option(args);
     }
 
-    if let Some(args) = self.runtime_args.and_then(|s| shlex::split(&s)) {
+    let args = split_args(alias.runtime_args.as_ref().map(String::as_str), runtime_args)?;
+    if !args.is_empty() {
       parameter.runtime_option(args);
     }
 
+    if let Some(stdin) = stdin {
+      parameter.stdin(stdin);
+    }
+
+    let backend = backend::from_config(config)?;
+
+    // The compiler list is fetched only when there is something to check against it.
+    let validate = !self.no_validate &&
+                   (!options.is_empty() || !self.shortcuts.is_empty() || !presets.is_empty() || self.strict ||
+                    self.explain);
+    let compilers = if validate {
+      backend.get_compiler_info()?
+    } else {
+      Vec::new()
+    };
+
+    if let Some(info) = compilers.iter().find(|c| c.name == alias.compiler) {
+      preset::check(&presets, info)?;
+    } else if self.no_validate && !presets.is_empty() {
+      warn!("presets are not checked against compiler `{}` with --no-validate", alias.compiler);
+    }
+
+    let option_set = if !validate {
+      if !self.shortcuts.is_empty() {
+        bail!("--std, --boost, --warnings, --optimize and --sprout cannot be used with --no-validate");
+      }
+      // Without the switches of the compiler, the options cannot be merged by group.
+      if let Some(options) = options.pop() {
+        parameter.options(options);
+      }
+      None
+    } else {
+      Some(resolve_options(&compilers,
+                           &options,
+                           &self.shortcuts,
+                           self.strict,
+                           &mut parameter)?)
+    };
+
     if let Some(files) = self.files {
       parameter.codes(files);
     }
 
-    if let Some(stdin) = self.stdin {
-      parameter.stdin(stdin);
+    if self.explain {
+      match option_set {
+        Some(option_set) => explain(&option_set, &parameter),
+        None => bail!("--explain cannot be used with --no-validate"),
+      }
+      return Ok(0);
     }
 
-    // Show request information
-    println!("[Request info]");
-    println!("compiler = {:?}", parameter.compiler);
-    if let Some(ref options) = parameter.options {
-      println!("options = {:?}", options);
-    }
-    if let Some(ref option_raw) = parameter.compiler_option_raw {
-      println!("compiler_options = {:?}",
-               option_raw.split("\n").collect::<Vec<_>>());
+    if let Some(path) = self.emit_request {
+      emit_request(&parameter, path)?;
     }
-    if let Some(ref option_raw) = parameter.runtime_option_raw {
-      println!("runtime_options = {:?}",
-               option_raw.split("\n").collect::<Vec<_>>());
+    if self.dry_run {
+      if self.emit_request.is_none() {
+        emit_request(&parameter, "-")?;
+      }
+      return Ok(0);
     }
-    println!("");
 
-    // Send request
-    let wandbox = Wandbox::new(config.url);
-    let response = wandbox.compile(parameter, self.verbose)?;
+    send_compile(&*backend, parameter, self.browse, None)
+  }
 
-    // Show compile response
-    if let Some(ref message) = response.program_message {
-      println!("[Program message]");
-      println!("{}", message);
+  fn read_code(&self) -> ::Result<String> {
+    let mut code = String::new();
+    if self.filename != "-" {
+      File::open(self.filename)
+        .and_then(|mut f| f.read_to_string(&mut code))
+        .chain_err(|| format!("failed to read {}", self.filename))?;
     } else {
-      println!("[Compiler message]");
-      println!("{}", response.compiler_message.as_ref().unwrap());
+      io::stdin().read_to_string(&mut code).chain_err(|| "failed to read the standard input")?;
     }
-    println!("[Program exited with status {}]", response.status);
+    Ok(code)
+  }
 
-    if let Some(url) = response.url {
-      println!("[Permlink URL]");
-      println!("{}", url);
-      if self.browse {
-        open_browser(url)?;
+  fn read_stdin(&self) -> ::Result<Option<String>> {
+    let mut input = String::new();
+    match self.stdin {
+      Some(Stdin::Inline(stdin)) => return Ok(Some(stdin.to_owned())),
+      Some(Stdin::File(path)) => {
+        File::open(path)
+          .and_then(|mut f| f.read_to_string(&mut input))
+          .chain_err(|| format!("failed to read {}", path))?;
+      }
+      Some(Stdin::Pipe) => {
+        io::stdin().read_to_string(&mut input)?;
       }
+      Some(Stdin::Tty) => {
+        let eof = if cfg!(windows) { "Ctrl-Z and Enter" } else { "Ctrl-D" };
+        writeln!(&mut io::stderr(),
+                 "Type the standard input of the program, and press {} to finish:",
+                 eof)?;
+        io::stdin().read_to_string(&mut input)?;
+      }
+      None => return Ok(None),
+    }
+    Ok(Some(input))
+  }
+
+  fn guess_language<'l>(&self, languages: &'l Languages, code: &str) -> Option<&'l Language> {
+    if self.filename != "-" {
+      PathBuf::from(self.filename)
+        .extension()
+        .map(|ext| ext.to_string_lossy())
+        .and_then(|ext| languages.find_by_extension(ext.borrow()))
+    } else {
+      None
     }
+    .or_else(|| languages.detect(code))
+  }
+}
+
+
+pub struct RunApp<'a> {
+  target: Option<&'a str>,
+  manifest: Option<&'a str>,
+  permlink: bool,
+  browse: bool,
+  strict: bool,
+  explain: bool,
+  dry_run: bool,
+  emit_request: Option<&'a str>,
+}
+
+impl<'c> RunApp<'c> {
+  fn make_app<'a, 'b: 'a>(app: clap::App<'a, 'b>) -> clap::App<'a, 'b> {
+    app.about("Post a program described in the project manifest (wan.json or wan.toml)")
+       .args_from_usage(r#"
+        [target]                        'Target name in the manifest'
+        --manifest=[manifest]           'Path of the manifest'
+        --permlink                      'Generate permlink and output URL at end'
+        --browse                        'Open permlink URL'
+        --strict                        'Fail when raw arguments are not accepted by the compiler'
+        --explain                       'Show the effective compile command instead of sending a request'
+        --dry-run                       'Build the request without sending it'
+        --emit-request=[path]           'Save the request as JSON (`-` for standard output)'
+      "#)
+  }
+}
 
-    Ok(response.status)
+impl<'a, 'b: 'a> From<&'b clap::ArgMatches<'a>> for RunApp<'a> {
+  fn from(m: &'b clap::ArgMatches<'a>) -> RunApp<'a> {
+    RunApp {
+      target: m.value_of("target"),
+      manifest: m.value_of("manifest"),
+      permlink: m.is_present("permlink"),
+      browse: m.is_present("browse"),
+      strict: m.is_present("strict"),
+      explain: m.is_present("explain"),
+      dry_run: m.is_present("dry-run"),
+      emit_request: m.value_of("emit-request"),
+    }
   }
+}
+
+impl<'a> RunApp<'a> {
+  fn run(self, config: &Config) -> Result<i32, ::Error> {
+    let languages = Languages::with_config(&config.languages)?;
+
+    let path = match self.manifest {
+      Some(path) => PathBuf::from(path),
+      None => {
+        match Manifest::find()? {
+          Some(path) => path,
+          None => {
+            bail!("{} is not found in the current directory or its parents",
+                  util::str_join(manifest::MANIFEST_NAMES, " or "))
+          }
+        }
+      }
+    };
+    debug!("loading the manifest {}", path.display());
+    let manifest = Manifest::load(&path)?;
+    let (_, target) = manifest.target(self.target)?;
+    let base_dir = manifest.base_dir();
+
+    let backend = backend::from_config(config)?;
 
-  fn read_code(&self) -> ::Result<String> {
     let mut code = String::new();
-    if self.filename != "-" {
-      File::open(self.filename)?.read_to_string(&mut code)?;
+    let main = target.main_path(base_dir);
+    File::open(&main).chain_err(|| format!("failed to read {}", main.display()))?.read_to_string(&mut code)?;
+
+    // The compiler list is fetched only when the version or the options are resolved with it.
+    let mut compilers = Vec::new();
+    if target.compiler.is_none() && target.version.is_some() {
+      compilers = backend.get_compiler_info()?;
+    }
+    let mut alias = config.expand_compiler(&target.resolve_compiler(&languages, &compilers)?);
+    let presets = Presets::with_config(&config.presets);
+    let presets = match target.preset {
+      Some(ref names) => presets.resolve(names, &alias.compiler)?,
+      None => Vec::new(),
+    };
+    preset::apply(&mut alias, &presets);
+    info!("compiler: {}", alias.compiler);
+    let preset_options = preset::options(&presets);
+    let options = option_layers(&alias,
+                                &[preset_options.as_ref().map(String::as_str),
+                                  target.options.as_ref().map(String::as_str)],
+                                config);
+    let validate = !options.is_empty() || !presets.is_empty() || self.strict || self.explain;
+    if validate && compilers.is_empty() {
+      compilers = backend.get_compiler_info()?;
+    }
+    if let Some(info) = compilers.iter().find(|c| c.name == alias.compiler) {
+      preset::check(&presets, info)?;
+    }
+    let mut parameter = wandbox::Parameter::new(code, alias.compiler.clone());
+    parameter.save_permlink(self.browse || self.permlink);
+
+    let args = split_args(alias.compiler_args.as_ref().map(String::as_str),
+                          target.compiler_args.as_ref().map(String::as_str))?;
+    if !args.is_empty() {
+      parameter.compiler_option(args);
+    }
+
+    let args = split_args(alias.runtime_args.as_ref().map(String::as_str),
+                          target.runtime_args.as_ref().map(String::as_str))?;
+    if !args.is_empty() {
+      parameter.runtime_option(args);
+    }
+
+    if let Some(path) = target.stdin_path(base_dir) {
+      let mut stdin = String::new();
+      File::open(&path).chain_err(|| format!("failed to read {}", path.display()))?.read_to_string(&mut stdin)?;
+      parameter.stdin(stdin);
+    }
+
+    let option_set = if validate {
+      Some(resolve_options(&compilers, &options, &[], self.strict, &mut parameter)?)
     } else {
-      io::stdin().read_to_string(&mut code)?;
+      None
+    };
+
+    let files = target.file_paths(base_dir)?;
+    if !files.is_empty() {
+      parameter.codes(files.iter().map(|path| path.to_string_lossy()));
     }
-    Ok(code)
+
+    match option_set {
+      Some(ref option_set) if self.explain => {
+        explain(option_set, &parameter);
+        return Ok(0);
+      }
+      _ => (),
+    }
+
+    if let Some(path) = self.emit_request {
+      emit_request(&parameter, path)?;
+    }
+    if self.dry_run {
+      if self.emit_request.is_none() {
+        emit_request(&parameter, "-")?;
+      }
+      return Ok(0);
+    }
+
+    send_compile(&*backend, parameter, self.browse, None)
+  }
+}
+
+/// Returns the options of a compiler alias followed by the given ones, in order of precedence.
+///
+/// Without any, the default options of the compiler in the configuration are used.
+fn option_layers(alias: &CompilerAlias, options: &[Option<&str>], config: &Config) -> Vec<String> {
+  let layers: Vec<String> = alias.options
+                                 .iter()
+                                 .cloned()
+                                 .chain(options.iter().filter_map(|options| options.map(ToOwned::to_owned)))
+                                 .collect();
+  if layers.is_empty() {
+    config.options.get(&alias.compiler).cloned().into_iter().collect()
+  } else {
+    layers
+  }
+}
+
+/// Splits the arguments of a compiler alias and the given ones into words.
+fn split_args(alias: Option<&str>, args: Option<&str>) -> ::Result<Vec<String>> {
+  let mut words = Vec::new();
+  for args in alias.into_iter().chain(args) {
+    match shlex::split(args) {
+      Some(w) => words.extend(w),
+      None => bail!("invalid quotation in arguments: {}", args),
+    }
+  }
+  Ok(words)
+}
+
+/// Checks the options against the switches of the compiler, and sets them to `parameter`.
+///
+/// Each layer of options, and then the shortcuts, replace the earlier choices in the same group.
+fn resolve_options<'c>(compilers: &'c [CompilerInfo],
+                       layers: &[String],
+                       shortcuts: &[Shortcut],
+                       strict: bool,
+                       parameter: &mut wandbox::Parameter)
+                       -> ::Result<OptionSet<'c>> {
+  let info = match compilers.iter().find(|c| c.name == parameter.compiler) {
+    Some(info) => info,
+    None => bail!(::ErrorKind::UnknownCompiler(parameter.compiler.clone())),
+  };
+
+  let mut options = OptionSet::new(info);
+  options.apply_layers(layers)?;
+  options.start_layer();
+  for &shortcut in shortcuts {
+    options.apply_shortcut(shortcut)?;
+  }
+  parameter.options(options.to_options_string());
+
+  for message in options::check_raw_options(info, parameter) {
+    if strict {
+      bail!(message);
+    }
+    warn!("{}", message);
+  }
+
+  Ok(options)
+}
+
+/// Shows the command lines which Wandbox will run.
+fn explain(option_set: &OptionSet, parameter: &wandbox::Parameter) {
+  println!("[Compile command]");
+  println!("$ {}", options::compile_command(option_set, parameter));
+  if let Some(ref option_raw) = parameter.runtime_option_raw {
+    println!("[Runtime arguments]");
+    println!("{}", util::shell_join(option_raw.split("\n")));
+  }
+}
+
+/// Writes the request as JSON to a file, or to the standard output for `-`.
+fn emit_request(parameter: &wandbox::Parameter, path: &str) -> ::Result<()> {
+  if path == "-" {
+    util::dump_to_json(parameter)?;
+    println!();
+    return Ok(());
+  }
+  let json = serde_json::to_string_pretty(parameter)? + "\n";
+  File::create(path)
+    .and_then(|mut f| f.write_all(json.as_bytes()))
+    .chain_err(|| format!("failed to write {}", path))?;
+  Ok(())
+}
+
+/// Sends the request, and shows the result.
+fn send_compile(backend: &Backend,
+                parameter: wandbox::Parameter,
+                browse: bool,
+                save_response: Option<&str>)
+                -> ::Result<i32> {
+  // Show request information
+  println!("[Request info]");
+  println!("compiler = {:?}", parameter.compiler);
+  if let Some(ref options) = parameter.options {
+    println!("options = {:?}", options);
+  }
+  if let Some(ref option_raw) = parameter.compiler_option_raw {
+    println!("compiler_options = {:?}",
+             option_raw.split("\n").collect::<Vec<_>>());
+  }
+  if let Some(ref option_raw) = parameter.runtime_option_raw {
+    println!("runtime_options = {:?}",
+             option_raw.split("\n").collect::<Vec<_>>());
+  }
+  println!();
+
+  // Send request
+  let response = backend.compile(parameter)?;
+  if let Some(path) = save_response {
+    let json = serde_json::to_string_pretty(&response)? + "\n";
+    File::create(path)
+      .and_then(|mut f| f.write_all(json.as_bytes()))
+      .chain_err(|| format!("failed to write {}", path))?;
+  }
+
+  // Show compile response
+  if let Some(ref message) = response.program_message {
+    println!("[Program message]");
+    println!("{}", message);
+  } else {
+    println!("[Compiler message]");
+    println!("{}", response.compiler_message.as_ref().unwrap());
+  }
+  println!("[Program exited with status {}]", response.status);
+
+  if let Some(url) = response.url {
+    println!("[Permlink URL]");
+    println!("{}", url);
+    if browse {
+      open_browser(url)?;
+    }
+  }
+
+  Ok(response.status)
+}
+
+
+pub struct LanguagesApp<'a> {
+  check: bool,
+  marker: PhantomData<&'a usize>,
+}
+
+impl<'c> LanguagesApp<'c> {
+  fn make_app<'a, 'b: 'a>(app: clap::App<'a, 'b>) -> clap::App<'a, 'b> {
+    app.about("List languages with their default compiler and file extensions")
+       .arg_from_usage("-c, --check 'Check the mapping against the compilers of Wandbox'")
+  }
+}
+
+impl<'a, 'b: 'a> From<&'b clap::ArgMatches<'a>> for LanguagesApp<'a> {
+  fn from(m: &'b clap::ArgMatches<'a>) -> LanguagesApp<'a> {
+    LanguagesApp {
+      check: m.is_present("check"),
+      marker: PhantomData,
+    }
+  }
+}
+
+impl<'a> LanguagesApp<'a> {
+  fn run(self, config: &Config) -> Result<i32, ::Error> {
+    let languages = Languages::with_config(&config.languages)?;
+
+    let compilers = if self.check {
+      backend::from_config(config)?.get_compiler_info()?
+    } else {
+      Vec::new()
+    };
+
+    let mut status = 0;
+    let mut sorted: Vec<_> = languages.iter().collect();
+    sorted.sort_by_key(|l| l.name.to_lowercase());
+    for language in sorted {
+      println!("[{}]", language.name);
+      println!("- compiler: {}", language.compiler);
+      println!("- extensions: {}", util::str_join(&language.extensions, ", "));
+      if self.check {
+        if !compilers.iter().any(|c| c.language == language.name) {
+          println!("- warning: no compiler for this language is available");
+          status = 1;
+        } else if !compilers.iter().any(|c| c.name == language.compiler) {
+          println!("- warning: compiler `{}` is not available", language.compiler);
+          status = 1;
+        }
+      }
+      println!();
+    }
+
+    let mut missing: Vec<_> = compilers.iter()
+                                       .map(|c| c.language.as_str())
+                                       .filter(|name| languages.get(name).is_none())
+                                       .collect();
+    missing.sort();
+    missing.dedup();
+    for name in missing {
+      println!("warning: language `{}` has no mapping", name);
+      status = 1;
+    }
+
+    Ok(status)
+  }
+}
+
+
+pub struct SendApp<'a> {
+  request: &'a str,
+  save_response: Option<&'a str>,
+  browse: bool,
+}
+
+impl<'c> SendApp<'c> {
+  fn make_app<'a, 'b: 'a>(app: clap::App<'a, 'b>) -> clap::App<'a, 'b> {
+    app.about("Post a request saved by --emit-request")
+       .args_from_usage(r#"
+        <request>                       'Request JSON (`-` for standard input)'
+        --save-response=[path]          'Save the response as JSON'
+        --browse                        'Open permlink URL'
+      "#)
+  }
+}
+
+impl<'a, 'b: 'a> From<&'b clap::ArgMatches<'a>> for SendApp<'a> {
+  fn from(m: &'b clap::ArgMatches<'a>) -> SendApp<'a> {
+    SendApp {
+      request: m.value_of("request").unwrap(),
+      save_response: m.value_of("save-response"),
+      browse: m.is_present("browse"),
+    }
+  }
+}
+
+impl<'a> SendApp<'a> {
+  fn run(self, config: &Config) -> Result<i32, ::Error> {
+    let mut parameter: wandbox::Parameter = if self.request == "-" {
+      serde_json::from_reader(io::stdin()).chain_err(|| "failed to parse the request")?
+    } else {
+      let file = File::open(self.request).chain_err(|| format!("failed to open {}", self.request))?;
+      serde_json::from_reader(file).chain_err(|| format!("failed to parse {}", self.request))?
+    };
+    if self.browse {
+      parameter.save_permlink(true);
+    }
+
+    let backend = backend::from_config(config)?;
+    send_compile(&*backend, parameter, self.browse, self.save_response)
+  }
+}
+
+
+pub struct PresetsApp<'a> {
+  compiler: Option<&'a str>,
+}
+
+impl<'c> PresetsApp<'c> {
+  fn make_app<'a, 'b: 'a>(app: clap::App<'a, 'b>) -> clap::App<'a, 'b> {
+    app.about("List presets of options and arguments")
+       .arg_from_usage("--compiler=[compiler] 'Show only the presets available for the compiler'")
+  }
+}
+
+impl<'a, 'b: 'a> From<&'b clap::ArgMatches<'a>> for PresetsApp<'a> {
+  fn from(m: &'b clap::ArgMatches<'a>) -> PresetsApp<'a> {
+    PresetsApp { compiler: m.value_of("compiler") }
+  }
+}
+
+impl<'a> PresetsApp<'a> {
+  fn run(self, config: &Config) -> Result<i32, ::Error> {
+    let compiler = self.compiler.map(|name| config.expand_compiler(name).compiler);
+    let presets = Presets::with_config(&config.presets);
+    for preset in presets.iter() {
+      if let Some(ref compiler) = compiler {
+        if !preset.applies_to(compiler) {
+          continue;
+        }
+      }
+      if preset.compilers.is_empty() {
+        println!("[{}]", preset.name);
+      } else {
+        println!("[{}] ({})", preset.name, util::str_join(&preset.compilers, ", "));
+      }
+      if let Some(ref description) = preset.description {
+        println!("- description: {}", description);
+      }
+      if let Some(ref options) = preset.options {
+        println!("- options: {}", options);
+      }
+      if let Some(ref args) = preset.compiler_args {
+        println!("- compile-args: {}", args);
+      }
+      if let Some(ref args) = preset.runtime_args {
+        println!("- runtime-args: {}", args);
+      }
+      println!();
+    }
+    Ok(0)
+  }
+}
+
+
+pub struct PingApp<'a> {
+  marker: PhantomData<&'a usize>,
+}
+
+impl<'c> PingApp<'c> {
+  fn make_app<'a, 'b: 'a>(app: clap::App<'a, 'b>) -> clap::App<'a, 'b> {
+    app.about("Measure the latency of each endpoint and show which one is used")
+  }
+}
+
+impl<'a, 'b: 'a> From<&'b clap::ArgMatches<'a>> for PingApp<'a> {
+  fn from(_: &'b clap::ArgMatches<'a>) -> PingApp<'a> {
+    PingApp { marker: PhantomData }
+  }
+}
+
+impl<'a> PingApp<'a> {
+  fn run(self, config: &Config) -> Result<i32, ::Error> {
+    let wandbox = Wandbox::from_config(config);
+    let mut used = None;
+    for endpoint in wandbox.endpoints() {
+      match wandbox.ping(endpoint) {
+        Ok((elapsed, count)) => {
+          let millis = elapsed.as_secs() * 1000 + (elapsed.subsec_nanos() / 1000000) as u64;
+          println!("{}: ok, {} ms ({} compilers)", endpoint, millis, count);
+          used = used.or(Some(endpoint));
+        }
+        Err(err) => println!("{}: unavailable ({})", endpoint, err),
+      }
+    }
+
+    match used {
+      Some(endpoint) => {
+        println!("using {}", endpoint);
+        Ok(0)
+      }
+      None => {
+        println!("no endpoint is available");
+        Ok(1)
+      }
+    }
+  }
+}
+
+
+pub struct MockServerApp<'a> {
+  addr: &'a str,
+  list: Option<&'a str>,
+  rules: Option<&'a str>,
+}
+
+impl<'c> MockServerApp<'c> {
+  fn make_app<'a, 'b: 'a>(app: clap::App<'a, 'b>) -> clap::App<'a, 'b> {
+    app.about("Run a local stand-in of the Wandbox API for testing")
+       .arg(clap::Arg::from_usage("--addr=[addr]   'Address to listen on'").default_value("127.0.0.1:8080"))
+       .arg_from_usage("--list=[file]   'Compiler list to serve as list.json (default: a compiler for each language)'")
+       .arg_from_usage("--rules=[file]  'JSON file of the rules to answer compile requests'")
+  }
+}
+
+impl<'a, 'b: 'a> From<&'b clap::ArgMatches<'a>> for MockServerApp<'a> {
+  fn from(m: &'b clap::ArgMatches<'a>) -> MockServerApp<'a> {
+    MockServerApp {
+      addr: m.value_of("addr").unwrap(),
+      list: m.value_of("list"),
+      rules: m.value_of("rules"),
+    }
+  }
+}
+
+impl<'a> MockServerApp<'a> {
+  fn run(self) -> Result<i32, ::Error> {
+    let mut server = match self.list {
+      Some(path) => MockServer::from_file(path)?,
+      None => MockServer::new(mock::default_list())?,
+    };
+    if let Some(path) = self.rules {
+      server.load_rules(path)?;
+    }
+    let running = server.start(self.addr)?;
+    println!("listening on {}", running.url());
+    running.wait();
+    Ok(0)
+  }
+}
+
+
+pub struct ServeApp<'a> {
+  addr: &'a str,
+  permlink_dir: Option<&'a str>,
+}
+
+impl<'c> ServeApp<'c> {
+  fn make_app<'a, 'b: 'a>(app: clap::App<'a, 'b>) -> clap::App<'a, 'b> {
+    app.about("Serve the Wandbox API with the compilers of the local backend")
+       .arg(clap::Arg::from_usage("--addr=[addr]          'Address to listen on'").default_value("127.0.0.1:3500"))
+       .arg_from_usage("--permlink-dir=[dir]   'Directory to save permlinks (default: <config dir>/permlinks)'")
+  }
+}
+
+impl<'a, 'b: 'a> From<&'b clap::ArgMatches<'a>> for ServeApp<'a> {
+  fn from(m: &'b clap::ArgMatches<'a>) -> ServeApp<'a> {
+    ServeApp {
+      addr: m.value_of("addr").unwrap(),
+      permlink_dir: m.value_of("permlink-dir"),
+    }
+  }
+}
+
+impl<'a> ServeApp<'a> {
+  fn run(self, config: &Config) -> Result<i32, ::Error> {
+    let permlink_dir = match self.permlink_dir {
+      Some(dir) => PathBuf::from(dir),
+      None => config::user_config_file()?.with_file_name("permlinks"),
+    };
+    let server = Server::new(Local::from_config(config), permlink_dir);
+    let listening = server.start(self.addr)?;
+    println!("listening on http://{}", listening.socket);
+    // `Listening` joins the threads of the server when dropped.
+    drop(listening);
+    Ok(0)
+  }
+}
+
+
+pub struct ProxyApp<'a> {
+  addr: &'a str,
+  rate_limit: &'a str,
+  list_ttl: &'a str,
+  cache_size: &'a str,
+  cache_dir: Option<&'a str>,
+}
+
+impl<'c> ProxyApp<'c> {
+  fn make_app<'a, 'b: 'a>(app: clap::App<'a, 'b>) -> clap::App<'a, 'b> {
+    app.about("Forward the Wandbox API to the upstream, caching the results")
+       .arg(clap::Arg::from_usage("--addr=[addr]          'Address to listen on'").default_value("127.0.0.1:3600"))
+       .arg(clap::Arg::from_usage("--rate-limit=[count]   'Requests per minute forwarded for each client'")
+              .default_value("60"))
+       .arg(clap::Arg::from_usage("--list-ttl=[seconds]   'How long the compiler list is cached'")
+              .default_value("3600"))
+       .arg(clap::Arg::from_usage("--cache-size=[count]   'Number of results cached in memory'").default_value("1000"))
+       .arg_from_usage("--cache-dir=[dir]      'Directory to keep the cached results across restarts'")
   }
+}
+
+impl<'a, 'b: 'a> From<&'b clap::ArgMatches<'a>> for ProxyApp<'a> {
+  fn from(m: &'b clap::ArgMatches<'a>) -> ProxyApp<'a> {
+    ProxyApp {
+      addr: m.value_of("addr").unwrap(),
+      rate_limit: m.value_of("rate-limit").unwrap(),
+      list_ttl: m.value_of("list-ttl").unwrap(),
+      cache_size: m.value_of("cache-size").unwrap(),
+      cache_dir: m.value_of("cache-dir"),
+    }
+  }
+}
 
-  fn guess_compiler(&self) -> Option<String> {
-    self.compiler
-        .or_else(|| if self.filename != "-" {
-                   PathBuf::from(self.filename)
-                     .extension()
-                     .map(|ext| ext.to_string_lossy())
-                     .and_then(|ext| language::get_compiler_from_ext(ext.borrow()))
-                 } else {
-                   None
-                 })
-        .map(ToOwned::to_owned)
+impl<'a> ProxyApp<'a> {
+  fn run(self, config: &Config) -> Result<i32, ::Error> {
+    let rate_limit: u32 = self.rate_limit
+                              .parse()
+                              .chain_err(|| format!("invalid rate limit `{}`", self.rate_limit))?;
+    if rate_limit == 0 {
+      bail!("rate limit must be positive");
+    }
+    let list_ttl: u64 = self.list_ttl.parse().chain_err(|| format!("invalid TTL `{}`", self.list_ttl))?;
+    let cache_size = self.cache_size.parse().chain_err(|| format!("invalid cache size `{}`", self.cache_size))?;
+    let options = ProxyOptions {
+      rate_limit: rate_limit,
+      list_ttl: Duration::from_secs(list_ttl),
+      cache_size: cache_size,
+      cache_dir: self.cache_dir.map(Cassette::record),
+    };
+    let proxy = Proxy::new(Wandbox::from_config(config), options);
+    let listening = proxy.start(self.addr)?;
+    println!("listening on http://{}", listening.socket);
+    // `Listening` joins the threads of the server when dropped.
+    drop(listening);
+    Ok(0)
   }
 }
 
@@ -270,11 +1062,17 @@ impl<'a, 'b: 'a> From<&'b clap::ArgMatches<'a>> for PermlinkApp<'a> {
 }
 
 impl<'a> PermlinkApp<'a> {
-  fn run(self) -> Result<i32, ::Error> {
-    let config = config::Config::load()?;
+  fn run(self, config: &Config) -> Result<i32, ::Error> {
+    let backend = backend::from_config(config)?;
+    // The page is opened first, since some backends have only the page and not the result.
+    if self.browse {
+      match backend.permlink_url(self.link) {
+        Some(url) => open_browser(url)?,
+        None => bail!("the backend has no page to browse permlinks"),
+      }
+    }
 
-    let wandbox = Wandbox::new(config.url);
-    let s = wandbox.get_permlink(self.link)?;
+    let s = backend.get_permlink(self.link)?;
     let result: PermlinkResult = serde_json::from_str(&s)?;
 
     if self.dump {
@@ -283,11 +1081,6 @@ impl<'a> PermlinkApp<'a> {
       println!("{}", result);
     }
 
-    if self.browse {
-      let url = wandbox.permlink_url(self.link);
-      open_browser(url)?;
-    }
-
     Ok(0)
   }
 }
@@ -322,46 +1115,420 @@ impl ::std::fmt::Display for PermlinkResult {
     if let Some(ref message) = self.result.program_message {
       writeln!(w, "[Program message]")?;
       writeln!(w, "{}", message)?;
-    } else {
+    } else if let Some(ref message) = self.result.compiler_message {
       writeln!(w, "[Compiler message]")?;
-      writeln!(w, "{}", self.result.compiler_message.as_ref().unwrap())?;
+      writeln!(w, "{}", message)?;
     }
     writeln!(w, "[Program exited with status {}]", self.result.status)
   }
 }
 
 
-pub enum App<'a> {
+pub struct ConfigApp<'a> {
+  action: ConfigAction<'a>,
+  project: bool,
+}
+
+enum ConfigAction<'a> {
+  Get(&'a str),
+  Set(&'a str, &'a str),
+  Unset(&'a str),
+  List,
+  Edit,
+  Validate(Vec<&'a str>),
+}
+
+impl<'c> ConfigApp<'c> {
+  fn make_app<'a, 'b: 'a>(app: clap::App<'a, 'b>) -> clap::App<'a, 'b> {
+    app.about("Get, set and validate settings in the configuration file")
+       .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+       .arg_from_usage("--project 'Edit .wan.json or .wan.toml of the project instead of the user configuration'")
+       .subcommand(clap::SubCommand::with_name("get")
+                     .about("Show the value of a key, e.g. `languages.C++.compiler`")
+                     .arg_from_usage("<key> 'Dotted key'"))
+       .subcommand(clap::SubCommand::with_name("set")
+                     .about("Set the value of a key")
+                     .arg_from_usage("<key>   'Dotted key'")
+                     .arg_from_usage("<value> 'String, comma-separated list or JSON, by the type of the key'"))
+       .subcommand(clap::SubCommand::with_name("unset")
+                     .about("Remove a key")
+                     .arg_from_usage("<key> 'Dotted key'"))
+       .subcommand(clap::SubCommand::with_name("list").about("Show all values and where each came from"))
+       .subcommand(clap::SubCommand::with_name("edit").about("Open the configuration file in $VISUAL or $EDITOR"))
+       .subcommand(clap::SubCommand::with_name("validate")
+                     .about("Check configuration files (by default, the ones in use)")
+                     .arg_from_usage("[files]... 'Files to check'"))
+  }
+}
+
+impl<'a, 'b: 'a> From<&'b clap::ArgMatches<'a>> for ConfigApp<'a> {
+  fn from(m: &'b clap::ArgMatches<'a>) -> ConfigApp<'a> {
+    let action = match m.subcommand() {
+      ("get", Some(m)) => ConfigAction::Get(m.value_of("key").unwrap()),
+      ("set", Some(m)) => ConfigAction::Set(m.value_of("key").unwrap(), m.value_of("value").unwrap()),
+      ("unset", Some(m)) => ConfigAction::Unset(m.value_of("key").unwrap()),
+      ("list", Some(_)) => ConfigAction::List,
+      ("edit", Some(_)) => ConfigAction::Edit,
+      ("validate", Some(m)) => ConfigAction::Validate(m.values_of("files").map(|v| v.collect()).unwrap_or_default()),
+      _ => unreachable!(),
+    };
+    ConfigApp {
+      action: action,
+      project: m.is_present("project"),
+    }
+  }
+}
+
+impl<'a> ConfigApp<'a> {
+  /// Runs the command before loading the configuration, so that a broken file can be fixed.
+  fn run(self, load_options: &LoadOptions) -> Result<i32, ::Error> {
+    match self.action {
+      ConfigAction::Get(key) => {
+        let config = Config::load_with(load_options)?;
+        let entries = config.get(key)?;
+        if entries.is_empty() {
+          return Ok(1);
+        }
+        for entry in entries {
+          if entry.key == key {
+            println!("{}", entry.value.as_str().map(ToOwned::to_owned).unwrap_or(entry.value.to_string()));
+          } else {
+            println!("{} = {}", entry.key, entry.value);
+          }
+        }
+      }
+      ConfigAction::Set(key, value) => {
+        let mut file = ConfigFile::open(self.target(load_options)?)?;
+        file.set(key, value)?;
+        file.save()?;
+      }
+      ConfigAction::Unset(key) => {
+        let mut file = ConfigFile::open(self.target(load_options)?)?;
+        file.unset(key)?;
+        file.save()?;
+      }
+      ConfigAction::List => {
+        let config = Config::load_with(load_options)?;
+        print_entries(config.entries());
+      }
+      ConfigAction::Edit => {
+        let path = self.target(load_options)?;
+        let file = ConfigFile::open(&path)?;
+        if !path.exists() {
+          file.save()?;
+        }
+        open_editor(&path)?;
+        return check_config_files(&[path]);
+      }
+      ConfigAction::Validate(ref files) if !files.is_empty() => {
+        let paths: Vec<_> = files.iter().map(PathBuf::from).collect();
+        return check_config_files(&paths);
+      }
+      ConfigAction::Validate(_) => {
+        let mut paths = Vec::new();
+        let user = match load_options.path {
+          Some(ref path) => path.clone(),
+          None => config::user_config_file()?,
+        };
+        if user.is_file() {
+          paths.push(user);
+        }
+        if let Some(path) = config::find_project_config()? {
+          paths.push(path);
+        }
+        if paths.is_empty() {
+          println!("no configuration file is found");
+          return Ok(0);
+        }
+        return check_config_files(&paths);
+      }
+    }
+    Ok(0)
+  }
+
+  /// Returns the file to be edited.
+  fn target(&self, load_options: &LoadOptions) -> ::Result<PathBuf> {
+    if self.project {
+      return match config::find_project_config()? {
+               Some(path) => Ok(path),
+               None => Ok(::std::env::current_dir()?.join(config::PROJECT_CONFIG_NAMES[0])),
+             };
+    }
+    match load_options.path {
+      Some(ref path) => Ok(path.clone()),
+      None => config::user_config_file(),
+    }
+  }
+}
+
+fn check_config_files(paths: &[PathBuf]) -> ::Result<i32> {
+  let mut count = 0;
+  for path in paths {
+    let problems = config::validate_file(path)?;
+    if problems.is_empty() {
+      println!("{}: ok", path.display());
+    }
+    for problem in &problems {
+      match problem.line {
+        Some(line) => println!("{}:{}: {}", path.display(), line, problem.message),
+        None => println!("{}: {}", path.display(), problem.message),
+      }
+    }
+    count += problems.len();
+  }
+  if count > 0 {
+    bail!("found {} problem(s) in the configuration", count);
+  }
+  Ok(0)
+}
+
+fn open_editor(path: &PathBuf) -> ::Result<()> {
+  let editor = ::std::env::var("VISUAL")
+    .or_else(|_| ::std::env::var("EDITOR"))
+    .unwrap_or_else(|_| if cfg!(windows) { "notepad" } else { "vi" }.to_owned());
+  let mut words = match shlex::split(&editor) {
+    Some(ref words) if !words.is_empty() => words.clone(),
+    _ => bail!("invalid editor command: {}", editor),
+  };
+  let program = words.remove(0);
+  let status = ::std::process::Command::new(&program)
+    .args(&words)
+    .arg(path)
+    .status()
+    .chain_err(|| format!("failed to launch the editor `{}`", program))?;
+  if !status.success() {
+    bail!("the editor exited with {}", status);
+  }
+  Ok(())
+}
+
+/// Prints the values of the configuration, with the secrets masked.
+fn print_entries(entries: &[Entry]) {
+  for entry in entries {
+    if entry.key.ends_with("token") || entry.key.ends_with("password") {
+      println!("{} = \"********\"  # {}", entry.key, entry.source);
+    } else {
+      println!("{} = {}  # {}", entry.key, entry.value, entry.source);
+    }
+  }
+}
+
+
+pub enum Command<'a> {
   List(ListApp<'a>),
   Compile(CompileApp<'a>),
+  Run(RunApp<'a>),
+  Languages(LanguagesApp<'a>),
   Permlink(PermlinkApp<'a>),
+  Send(SendApp<'a>),
+  Presets(PresetsApp<'a>),
+  Ping(PingApp<'a>),
+  MockServer(MockServerApp<'a>),
+  Serve(ServeApp<'a>),
+  Proxy(ProxyApp<'a>),
+  Config(ConfigApp<'a>),
+}
+
+impl<'a, 'b: 'a> From<&'b clap::ArgMatches<'a>> for Command<'a> {
+  fn from(m: &'b clap::ArgMatches<'a>) -> Command<'a> {
+    match m.subcommand() {
+      ("list", Some(m)) => Command::List(m.into()),
+      ("compile", Some(m)) => Command::Compile(m.into()),
+      ("run", Some(m)) => Command::Run(m.into()),
+      ("languages", Some(m)) => Command::Languages(m.into()),
+      ("permlink", Some(m)) => Command::Permlink(m.into()),
+      ("send", Some(m)) => Command::Send(m.into()),
+      ("presets", Some(m)) => Command::Presets(m.into()),
+      ("ping", Some(m)) => Command::Ping(m.into()),
+      ("mock-server", Some(m)) => Command::MockServer(m.into()),
+      ("serve", Some(m)) => Command::Serve(m.into()),
+      ("proxy", Some(m)) => Command::Proxy(m.into()),
+      ("config", Some(m)) => Command::Config(m.into()),
+      _ => unreachable!(),
+    }
+  }
+}
+
+impl<'a> Command<'a> {
+  fn run(self, config: &Config) -> Result<i32, ::Error> {
+    match self {
+      Command::List(a) => a.run(config),
+      Command::Compile(a) => a.run(config),
+      Command::Run(a) => a.run(config),
+      Command::Languages(a) => a.run(config),
+      Command::Permlink(a) => a.run(config),
+      Command::Send(a) => a.run(config),
+      Command::Presets(a) => a.run(config),
+      Command::Ping(a) => a.run(config),
+      Command::MockServer(a) => a.run(),
+      Command::Serve(a) => a.run(config),
+      Command::Proxy(a) => a.run(config),
+      Command::Config(_) => unreachable!(),
+    }
+  }
+}
+
+
+/// Names of the subcommands, which cannot be overridden by aliases.
+const COMMANDS: &'static [&'static str] = &["list", "compile", "run", "languages", "permlink", "send", "presets", "ping",
+                                            "mock-server", "serve", "proxy", "config", "help"];
+
+/// Expands a command alias in the commandline arguments, before they are parsed by clap.
+///
+/// The configuration is loaded only when the subcommand is not a built-in one.
+pub fn expand_aliases(mut args: Vec<String>) -> ::Result<Vec<String>> {
+  let mut load_options = LoadOptions::default();
+  let mut i = 1;
+  while i < args.len() && args[i].starts_with('-') {
+    let (name, value) = match args[i].find('=') {
+      Some(pos) => (args[i][..pos].to_owned(), Some(args[i][pos + 1..].to_owned())),
+      None => (args[i].clone(), None),
+    };
+    i += 1;
+    if !["--config", "--profile", "--url", "--trace-http", "--cassette", "--log-format"].contains(&name.as_str()) {
+      continue;
+    }
+    let value = match value {
+      Some(value) => value,
+      None if i < args.len() => {
+        i += 1;
+        args[i - 1].clone()
+      }
+      None => return Ok(args),
+    };
+    match name.as_str() {
+      "--config" => load_options.path = Some(PathBuf::from(value)),
+      "--profile" => load_options.profile = Some(value),
+      "--url" => load_options.url = Some(value),
+      _ => (),
+    }
+  }
+  if i >= args.len() || COMMANDS.contains(&args[i].as_str()) {
+    return Ok(args);
+  }
+
+  let config = Config::load_with(&load_options)?;
+  let mut expanded = Vec::new();
+  while let Some(alias) = config.aliases.get(&args[i]) {
+    if expanded.contains(&args[i]) {
+      bail!("alias `{}` is recursive", args[i]);
+    }
+    let words = match shlex::split(alias) {
+      Some(ref words) if !words.is_empty() => words.clone(),
+      _ => bail!("invalid alias `{}`: {}", args[i], alias),
+    };
+    expanded.push(args[i].clone());
+    let rest = args.split_off(i + 1);
+    args.pop();
+    args.extend(words);
+    args.extend(rest);
+  }
+  Ok(args)
+}
+
+
+pub struct App<'a> {
+  verbosity: i32,
+  log_format: logger::Format,
+  load_options: LoadOptions,
+  show_config: bool,
+  command: Option<Command<'a>>,
 }
 
 impl<'c> App<'c> {
   pub fn make_app<'a, 'b: 'a>(app: clap::App<'a, 'b>) -> clap::App<'a, 'b> {
-    app.subcommand(ListApp::make_app(clap::SubCommand::with_name("list")))
+    app.arg_from_usage("--config=[config]   'Path of the configuration file'")
+       .arg_from_usage("--profile=[profile] 'Name of the profile to use'")
+       .arg_from_usage("--url=[url]         'URL of Wandbox'")
+       .arg_from_usage("--show-config       'Show the configuration and where each value came from'")
+       .arg_from_usage("--trace-http=[file] 'Record HTTP requests and responses to a HAR file'")
+       .arg_from_usage("--cassette=[dir]    'Record the responses to files in the directory'")
+       .arg(clap::Arg::from_usage("--replay 'Replay the responses recorded by --cassette, without the network'")
+              .requires("cassette"))
+       .arg(clap::Arg::from_usage("-v, --verbose... 'Show more log messages (-v: info, -vv: debug, -vvv: trace)'")
+              .global(true))
+       .arg(clap::Arg::from_usage("-q, --quiet 'Show only error messages'").global(true))
+       .arg(clap::Arg::from_usage("--log-format=[format] 'Format of log messages'").possible_values(&["text", "json"]))
+       .subcommand(ListApp::make_app(clap::SubCommand::with_name("list")))
        .subcommand(CompileApp::make_app(clap::SubCommand::with_name("compile")))
+       .subcommand(RunApp::make_app(clap::SubCommand::with_name("run")))
+       .subcommand(LanguagesApp::make_app(clap::SubCommand::with_name("languages")))
        .subcommand(PermlinkApp::make_app(clap::SubCommand::with_name("permlink")))
+       .subcommand(SendApp::make_app(clap::SubCommand::with_name("send")))
+       .subcommand(PresetsApp::make_app(clap::SubCommand::with_name("presets")))
+       .subcommand(PingApp::make_app(clap::SubCommand::with_name("ping")))
+       .subcommand(MockServerApp::make_app(clap::SubCommand::with_name("mock-server")))
+       .subcommand(ServeApp::make_app(clap::SubCommand::with_name("serve")))
+       .subcommand(ProxyApp::make_app(clap::SubCommand::with_name("proxy")))
+       .subcommand(ConfigApp::make_app(clap::SubCommand::with_name("config")))
   }
 }
 
 impl<'a, 'b: 'a> From<&'b clap::ArgMatches<'a>> for App<'a> {
   fn from(m: &'b clap::ArgMatches<'a>) -> App<'a> {
-    match m.subcommand() {
-      ("list", Some(m)) => App::List(m.into()),
-      ("compile", Some(m)) => App::Compile(m.into()),
-      ("permlink", Some(m)) => App::Permlink(m.into()),
-      _ => unreachable!(),
+    App {
+      verbosity: if global_occurrences(m, "quiet") > 0 {
+        -1
+      } else {
+        global_occurrences(m, "verbose") as i32
+      },
+      log_format: match m.value_of("log-format") {
+        Some("json") => logger::Format::Json,
+        _ => logger::Format::Text,
+      },
+      load_options: LoadOptions {
+        path: m.value_of("config").map(PathBuf::from),
+        profile: m.value_of("profile").map(ToOwned::to_owned),
+        url: m.value_of("url").map(ToOwned::to_owned),
+        trace_http: m.value_of("trace-http").map(PathBuf::from),
+        cassette: m.value_of("cassette").map(|dir| {
+          let mode = if m.is_present("replay") {
+            cassette::Mode::Replay
+          } else {
+            cassette::Mode::Record
+          };
+          Cassette::new(dir, mode)
+        }),
+        ..LoadOptions::default()
+      },
+      show_config: m.is_present("show-config"),
+      command: m.subcommand_name().map(|_| m.into()),
     }
   }
 }
 
+/// Counts a global flag, which is given before or after the subcommands.
+fn global_occurrences(m: &clap::ArgMatches, name: &str) -> u64 {
+  m.occurrences_of(name) + m.subcommand().1.map_or(0, |m| global_occurrences(m, name))
+}
+
 impl<'a> App<'a> {
   pub fn run(self) -> Result<i32, ::Error> {
-    match self {
-      App::List(a) => a.run(),
-      App::Compile(a) => a.run(),
-      App::Permlink(a) => a.run(),
+    // The proxy logs the traffic at the info level, which is shown unless `-q` is given.
+    let verbosity = match self.command {
+      Some(Command::Proxy(_)) if self.verbosity == 0 => 1,
+      _ => self.verbosity,
+    };
+    logger::init(verbosity, self.log_format)?;
+
+    let command = match self.command {
+      Some(Command::Config(app)) => return app.run(&self.load_options),
+      command => command,
+    };
+
+    let config = Config::load_with(&self.load_options)?;
+
+    if self.show_config {
+      print_entries(config.entries());
+      if command.is_none() {
+        return Ok(0);
+      }
+      println!();
+    }
+
+    match command {
+      Some(command) => command.run(&config),
+      None => bail!("a subcommand is required (see `wan help`)"),
     }
   }
 }
@@ -389,3 +1556,40 @@ fn open_browser<S: AsRef<str>>(s: S) -> ::Result<()> {
     .status()?;
   Ok(())
 }
+
+
+#[test]
+fn test_option_layers() {
+  let config = Config::default();
+  let alias = CompilerAlias {
+    compiler: "gcc-head".to_owned(),
+    options: Some("c++98,no-warning".to_owned()),
+    ..CompilerAlias::default()
+  };
+  let layers = option_layers(&alias, &[None, Some("gnu++1y")], &config);
+  assert_eq!(layers, ["c++98,no-warning", "gnu++1y"]);
+
+  let info: Vec<CompilerInfo> = serde_json::from_str(include_str!(concat!(env!("CARGO_MANIFEST_DIR"),
+                                                                          "/tests/list.json")))
+    .unwrap();
+  let mut parameter = wandbox::Parameter::new("", "gcc-head");
+  let options = resolve_options(&info, &layers, &[], false, &mut parameter).unwrap();
+  assert_eq!(options.names(), ["boost-1.55", "sprout", "gnu++1y"]);
+
+  // A preset is overridden by `--options`.
+  let layers = option_layers(&CompilerAlias::default(), &[Some("c++98"), None, Some("gnu++1y")], &config);
+  let options = resolve_options(&info, &layers, &[], false, &mut parameter).unwrap();
+  assert_eq!(options.names(), ["warning", "boost-1.55", "sprout", "gnu++1y"]);
+}
+
+#[test]
+fn test_global_occurrences() {
+  let verbosity = |args: &[&str]| {
+    let m = App::make_app(clap::App::new("wan")).get_matches_from(args);
+    App::from(&m).verbosity
+  };
+  assert_eq!(verbosity(&["wan", "-v", "list"]), 1);
+  assert_eq!(verbosity(&["wan", "list", "-vv"]), 2);
+  assert_eq!(verbosity(&["wan", "-v", "config", "get", "url", "-v"]), 2);
+  assert_eq!(verbosity(&["wan", "compile", "-q", "main.cpp"]), -1);
+}
@@ -0,0 +1,71 @@
+//! Services which compile and run the code, selected by `backend` in the configuration.
+
+use std::io::Read;
+
+use serde_json;
+
+use config::Config;
+use godbolt::Godbolt;
+use local::Local;
+use playground::Playground;
+use util;
+use wandbox::{CompilerInfo, Parameter, Response, Wandbox};
+
+/// Names of the available backends.
+pub const BACKENDS: &'static [&'static str] = &["wandbox", "local", "godbolt", "playground"];
+
+/// A service with the API of Wandbox.
+pub trait Backend {
+  /// Returns the list of the compilers, in the format of Wandbox's `list.json`.
+  fn get_compiler_info_raw(&self) -> ::Result<Box<Read>>;
+
+  fn get_compiler_info(&self) -> ::Result<Vec<CompilerInfo>> {
+    let res = self.get_compiler_info_raw()?;
+    Ok(serde_json::from_reader(res)?)
+  }
+
+  fn compile(&self, param: Parameter) -> ::Result<Response>;
+
+  /// Returns the JSON of the permlink, which contains `parameter` and `result`.
+  fn get_permlink(&self, _link: &str) -> ::Result<String> {
+    bail!("permlinks are not supported by the backend")
+  }
+
+  /// Returns the URL to browse the permlink.
+  fn permlink_url(&self, _link: &str) -> Option<String> {
+    None
+  }
+}
+
+impl Backend for Wandbox {
+  fn get_compiler_info_raw(&self) -> ::Result<Box<Read>> {
+    Wandbox::get_compiler_info_raw(self)
+  }
+
+  fn get_compiler_info(&self) -> ::Result<Vec<CompilerInfo>> {
+    Wandbox::get_compiler_info(self)
+  }
+
+  fn compile(&self, param: Parameter) -> ::Result<Response> {
+    Wandbox::submit(self, param)
+  }
+
+  fn get_permlink(&self, link: &str) -> ::Result<String> {
+    Wandbox::get_permlink(self, link)
+  }
+
+  fn permlink_url(&self, link: &str) -> Option<String> {
+    Some(Wandbox::permlink_url(self, link))
+  }
+}
+
+/// Creates the backend selected by the configuration.
+pub fn from_config(config: &Config) -> ::Result<Box<Backend>> {
+  match config.backend.as_ref().map(String::as_str) {
+    None | Some("wandbox") => Ok(Box::new(Wandbox::from_config(config))),
+    Some("local") => Ok(Box::new(Local::from_config(config))),
+    Some("godbolt") => Ok(Box::new(Godbolt::from_config(config))),
+    Some("playground") => Ok(Box::new(Playground::from_config(config))),
+    Some(name) => bail!("unknown backend `{}` (available: {})", name, util::str_join(BACKENDS, ", ")),
+  }
+}
@@ -1,22 +1,27 @@
 #[macro_use]
 extern crate clap;
-extern crate env_logger;
 extern crate wan;
 
 use std::io::Write;
-use wan::app::App;
+use wan::app::{self, App};
+use wan::report;
 
 fn main() {
-  env_logger::init().unwrap();
-
-  let ref matches = App::make_app(app_from_crate!()
-                                    .setting(clap::AppSettings::VersionlessSubcommands)
-                                    .setting(clap::AppSettings::SubcommandRequiredElseHelp))
-    .get_matches();
-  let app: App = matches.into();
-
-  match app.run() {
+  match run() {
     Ok(code) => std::process::exit(code),
-    Err(err) => writeln!(&mut std::io::stderr(), "failed with: {:?}", err).unwrap(),
+    Err(err) => {
+      let _ = write!(&mut std::io::stderr(), "{}", report::render(&err, report::detail_requested()));
+      std::process::exit(1);
+    }
   }
 }
+
+fn run() -> wan::Result<i32> {
+  let args = app::expand_aliases(std::env::args().collect())?;
+  let matches = App::make_app(app_from_crate!()
+                                 .setting(clap::AppSettings::VersionlessSubcommands)
+                                 .setting(clap::AppSettings::SubcommandRequiredElseHelp))
+    .get_matches_from(args);
+  let app: App = (&matches).into();
+  app.run()
+}
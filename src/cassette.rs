@@ -0,0 +1,138 @@
+//! Recording of responses to files, to replay them later without the network.
+//!
+//! Each response is saved in `<dir>/<key>.json`, where the key is the FNV-1a hash of the method,
+//! the path (without the endpoint) and the body of the request.
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde_json;
+
+use util;
+use ResultExt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+  /// Sends requests and saves their responses.
+  Record,
+  /// Serves the saved responses, and fails on requests which are not recorded.
+  Replay,
+}
+
+#[derive(Debug, Clone)]
+pub struct Cassette {
+  dir: PathBuf,
+  mode: Mode,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Recording {
+  request: RecordedRequest,
+  response: RecordedResponse,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RecordedRequest {
+  method: String,
+  path: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  body: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RecordedResponse {
+  status: u16,
+  body: String,
+}
+
+impl Cassette {
+  pub fn new<P: Into<PathBuf>>(dir: P, mode: Mode) -> Cassette {
+    Cassette {
+      dir: dir.into(),
+      mode: mode,
+    }
+  }
+
+  pub fn record<P: Into<PathBuf>>(dir: P) -> Cassette {
+    Self::new(dir, Mode::Record)
+  }
+
+  pub fn replay<P: Into<PathBuf>>(dir: P) -> Cassette {
+    Self::new(dir, Mode::Replay)
+  }
+
+  pub fn dir(&self) -> &Path {
+    &self.dir
+  }
+
+  pub fn mode(&self) -> Mode {
+    self.mode
+  }
+
+  /// Returns the key of a request, which names the file of its recording.
+  pub fn key(method: &str, path: &str, body: Option<&str>) -> String {
+    format!("{:016x}", util::fnv1a(&[method, path, body.unwrap_or("")]))
+  }
+
+  /// Returns the status and the body of the recorded response.
+  pub fn load(&self, method: &str, path: &str, body: Option<&str>) -> ::Result<(u16, String)> {
+    let file = self.file_of(method, path, body);
+    if !file.is_file() {
+      bail!(::ErrorKind::NoRecording(format!("{} {}", method, path), self.dir.display().to_string()));
+    }
+    let mut text = String::new();
+    File::open(&file)
+      .and_then(|mut f| f.read_to_string(&mut text))
+      .chain_err(|| format!("failed to read {}", file.display()))?;
+    let recording: Recording = serde_json::from_str(&text).chain_err(|| format!("failed to parse {}", file.display()))?;
+    Ok((recording.response.status, recording.response.body))
+  }
+
+  /// Saves the response of a request, replacing the previous recording.
+  pub fn save(&self, method: &str, path: &str, body: Option<&str>, status: u16, response: &str) -> ::Result<()> {
+    let recording = Recording {
+      request: RecordedRequest {
+        method: method.to_owned(),
+        path: path.to_owned(),
+        body: body.map(ToOwned::to_owned),
+      },
+      response: RecordedResponse {
+        status: status,
+        body: response.to_owned(),
+      },
+    };
+    let file = self.file_of(method, path, body);
+    let text = serde_json::to_string_pretty(&recording)? + "\n";
+    fs::create_dir_all(&self.dir)
+      .and_then(|_| File::create(&file))
+      .and_then(|mut f| f.write_all(text.as_bytes()))
+      .chain_err(|| format!("failed to write {}", file.display()))?;
+    Ok(())
+  }
+
+  fn file_of(&self, method: &str, path: &str, body: Option<&str>) -> PathBuf {
+    self.dir.join(format!("{}.json", Self::key(method, path, body)))
+  }
+}
+
+
+#[test]
+fn test_key() {
+  let key = Cassette::key("POST", "/api/compile.json", Some("{}"));
+  assert_eq!(key.len(), 16);
+  assert_eq!(key, Cassette::key("POST", "/api/compile.json", Some("{}")));
+  assert!(key != Cassette::key("POST", "/api/compile.json", Some("{ }")));
+  assert!(Cassette::key("GET", "/a", Some("b")) != Cassette::key("GET", "/ab", None));
+}
+
+#[test]
+fn test_record_replay() {
+  let dir = util::TempDir::new().unwrap();
+  let cassette = Cassette::record(dir.path());
+  cassette.save("GET", "/api/list.json", None, 200, "[]").unwrap();
+
+  let cassette = Cassette::replay(dir.path());
+  assert_eq!(cassette.load("GET", "/api/list.json", None).unwrap(), (200, "[]".to_owned()));
+  assert!(cassette.load("GET", "/api/permlink/abc", None).is_err());
+}
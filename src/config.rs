@@ -1,27 +1,1203 @@
 use shellexpand;
-use serde_json;
+use serde_json::{self, Map, Value};
 use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use backend;
+use cassette::Cassette;
+use local::LocalConfig;
+use preset::Preset;
+use util::{self, Either};
+use ResultExt;
 
 #[cfg(windows)]
 const CONFIG_DIR: &'static str = "~/AppData/Roaming/wan";
 #[cfg(not(windows))]
 const CONFIG_DIR: &'static str = "~/.config/wan";
 
-#[derive(Debug, Default, Deserialize)]
+/// File names of the project-local configuration, searched from the current directory upwards.
+pub const PROJECT_CONFIG_NAMES: &'static [&'static str] = &[".wan.json", ".wan.toml"];
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Config {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub url: Option<String>,
+  /// Endpoints tried in order when `url` is not available.
+  #[serde(default)]
+  pub endpoints: Vec<String>,
+  #[serde(default)]
+  pub languages: HashMap<String, LanguageConfig>,
+  /// Default value of `--options` for each compiler.
+  #[serde(default)]
+  pub options: HashMap<String, String>,
+  #[serde(default)]
+  pub tls: TlsConfig,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub auth: Option<AuthConfig>,
+  /// Command aliases, e.g. `"r": "compile --optimize"`.
+  #[serde(default)]
+  pub aliases: HashMap<String, String>,
+  /// Compiler aliases, given as the name of a compiler or a `CompilerAlias`.
+  #[serde(default, rename = "compiler-aliases")]
+  pub compiler_aliases: HashMap<String, Either<String, CompilerAlias>>,
+  /// User-defined presets, which take precedence over the built-in ones.
+  #[serde(default)]
+  pub presets: HashMap<String, Preset>,
+
+  /// Service to compile the code: `wandbox` (default), `local`, `godbolt` or `playground`.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub backend: Option<String>,
+  /// Toolchains used by the `local` backend.
+  #[serde(default)]
+  pub local: LocalConfig,
+
+  /// Name of the active profile.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub profile: Option<String>,
+  #[serde(default)]
+  pub profiles: HashMap<String, Profile>,
+
+  /// Path of the HAR file to record HTTP sessions, given by `--trace-http`.
+  #[serde(skip_serializing, skip_deserializing)]
+  pub trace_http: Option<PathBuf>,
+
+  /// Cassette to record or replay the responses, given by `--cassette` and `--replay`.
+  #[serde(skip_serializing, skip_deserializing)]
+  pub cassette: Option<Cassette>,
+
+  #[serde(skip_serializing, skip_deserializing)]
+  entries: Vec<Entry>,
+}
+
+/// Overrides of the built-in language mapping.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct LanguageConfig {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub compiler: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub extensions: Option<Vec<String>>,
+}
+
+/// Short name of a compiler, with options and arguments.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompilerAlias {
+  pub compiler: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub options: Option<String>,
+  #[serde(rename = "compile-args")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub compiler_args: Option<String>,
+  #[serde(rename = "runtime-args")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub runtime_args: Option<String>,
+}
+
+/// Named set of settings, which overrides the others when it is active.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Profile {
+  #[serde(skip_serializing_if = "Option::is_none")]
   pub url: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub backend: Option<String>,
+  #[serde(default)]
+  pub languages: HashMap<String, LanguageConfig>,
+  #[serde(default)]
+  pub options: HashMap<String, String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub tls: Option<TlsConfig>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub auth: Option<AuthConfig>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+  /// DER-encoded certificate added to the trusted roots.
+  #[serde(rename = "ca-file")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub ca_file: Option<String>,
+  /// PKCS #12 archive of the client certificate.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub identity: Option<String>,
+  #[serde(rename = "identity-password")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub identity_password: Option<String>,
+}
+
+/// Credentials sent in the `Authorization` header, as a bearer token or by basic authentication.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct AuthConfig {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub token: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub username: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub password: Option<String>,
+}
+
+/// Where a configuration value came from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+  Default,
+  File(PathBuf),
+  Env(String),
+  Profile(String),
+  CommandLine,
+}
+
+impl fmt::Display for Source {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Source::Default => write!(f, "default"),
+      Source::File(ref path) => write!(f, "{}", path.display()),
+      Source::Env(ref name) => write!(f, "environment variable {}", name),
+      Source::Profile(ref name) => write!(f, "profile {}", name),
+      Source::CommandLine => write!(f, "command line"),
+    }
+  }
+}
+
+/// A value of the merged configuration, e.g. `languages.C++.compiler`.
+#[derive(Debug, Clone)]
+pub struct Entry {
+  pub key: String,
+  pub value: Value,
+  pub source: Source,
+}
+
+/// Settings which affect the loading of the configuration, given from the command line.
+#[derive(Debug, Default)]
+pub struct LoadOptions {
+  /// Path of the user configuration, instead of the default location.
+  pub path: Option<PathBuf>,
+  pub profile: Option<String>,
+  pub url: Option<String>,
+  pub trace_http: Option<PathBuf>,
+  pub cassette: Option<Cassette>,
+  /// Directory where the project configuration is searched from, instead of the current directory.
+  pub dir: Option<PathBuf>,
+  /// Environment variables, instead of those of the process.
+  pub env: Option<HashMap<String, String>>,
 }
 
 impl Config {
   pub fn load() -> ::Result<Config> {
-    let path = format!("{}/config.json", CONFIG_DIR);
-    let path = shellexpand::full(&path)?;
-    if !::std::path::PathBuf::from(path.borrow() as &str).is_file() {
-      return Ok(Default::default());
-    }
-    let reader = ::std::fs::OpenOptions::new().read(true)
-      .open(path.borrow() as &str)?;
-    let config = serde_json::from_reader(reader)?;
+    Self::load_with(&LoadOptions::default())
+  }
+
+  /// Loads the configuration by merging the layers below, in order of increasing precedence:
+  ///
+  /// 1. built-in defaults
+  /// 2. the user configuration (`--config`, or `config.json` in `$XDG_CONFIG_HOME/wan` or `~/.config/wan`)
+  /// 3. `.wan.json` or `.wan.toml` found in the current directory or its parents
+  /// 4. the active profile, selected by `--profile`, `WAN_PROFILE` or `profile`
+  /// 5. environment variables, e.g. `WAN_URL` for `url` (see `env_key`)
+  /// 6. the command line
+  pub fn load_with(options: &LoadOptions) -> ::Result<Config> {
+    let env = match options.env {
+      Some(ref env) => env.clone(),
+      None => {
+        env::vars_os()
+          .filter_map(|(name, value)| match (name.into_string(), value.into_string()) {
+                        (Ok(name), Ok(value)) => Some((name, value)),
+                        _ => None,
+                      })
+          .collect()
+      }
+    };
+    let mut layers = Layers::default();
+
+    let mut defaults = Map::new();
+    // `url` has no default here, so that the configured `endpoints` come first and each backend
+    // falls back to its own service.
+    defaults.insert("backend".to_owned(), Value::String(backend::BACKENDS[0].to_owned()));
+    layers.merge(Value::Object(defaults), Source::Default);
+
+    let user_path = match options.path {
+      Some(ref path) => {
+        if !path.is_file() {
+          bail!("configuration file {} is not found", path.display());
+        }
+        Some(path.clone())
+      }
+      None => {
+        let path = user_config_file_in(env.get("XDG_CONFIG_HOME").map(String::as_str))?;
+        if path.is_file() { Some(path) } else { None }
+      }
+    };
+    if let Some(path) = user_path {
+      debug!("loading the user configuration {}", path.display());
+      layers.merge(read_layer(&path)?, Source::File(path));
+    }
+
+    let dir = match options.dir {
+      Some(ref dir) => dir.clone(),
+      None => env::current_dir()?,
+    };
+    if let Some(path) = find_project_config_from(dir) {
+      debug!("loading the project configuration {}", path.display());
+      layers.merge(read_layer(&path)?, Source::File(path));
+    }
+
+    let profile = options.profile
+                         .clone()
+                         .map(|name| (name, Source::CommandLine))
+                         .or_else(|| {
+                                    env.get("WAN_PROFILE")
+                                       .map(|name| (name.clone(), Source::Env("WAN_PROFILE".to_owned())))
+                                  })
+                         .or_else(|| layers.source_of("profile"));
+    if let Some((name, source)) = profile {
+      info!("using profile `{}` (selected by {})", name, source);
+      layers.apply_profile(name, source)?;
+    }
+
+    let mut names: Vec<_> = env.keys().filter(|name| name.starts_with("WAN_")).collect();
+    names.sort();
+    for name in names {
+      match env_key(name) {
+        // The profile is already applied above.
+        Some((ref key, _)) if key == "profile" => (),
+        Some((key, kind)) => {
+          let value = parse_value(&key, kind, &env[name]).chain_err(|| format!("invalid value of {}", name))?;
+          debug!("`{}` is overridden by {}", key, name);
+          layers.set(&key, value, Source::Env(name.clone()));
+        }
+        None => debug!("{} does not match any key of the configuration", name),
+      }
+    }
+
+    if let Some(ref url) = options.url {
+      layers.set("url", Value::String(url.clone()), Source::CommandLine);
+    }
+
+    let mut config: Config = serde_json::from_value(layers.value).chain_err(|| "invalid configuration")?;
+    config.entries = layers.entries;
+    config.trace_http = options.trace_http.clone();
+    config.cassette = options.cassette.clone();
     Ok(config)
   }
+
+  /// Returns all values of the configuration, with their origins.
+  pub fn entries(&self) -> &[Entry] {
+    &self.entries
+  }
+
+  /// Returns `url` followed by the other endpoints, without duplicates.
+  pub fn endpoints(&self) -> Vec<String> {
+    join_endpoints(self.url.as_ref(), &self.endpoints)
+  }
+
+  /// Returns the endpoints of a backend other than Wandbox, which are those set in the same layer as
+  /// `backend` (e.g. the same profile) or on the command line.
+  ///
+  /// The others, such as the top-level `url` or `WAN_URL` under a profile selecting the backend, are
+  /// of Wandbox, so that the backend falls back to its own service instead.
+  pub fn endpoints_for(&self, backend: &str) -> Vec<String> {
+    if backend == backend::BACKENDS[0] {
+      return self.endpoints();
+    }
+    let source_of = |key: &str| self.entries.iter().find(|e| e.key == key).map(|e| &e.source);
+    let backend = source_of("backend");
+    let applies = |key: &str| match (source_of(key), backend) {
+      (None, _) |
+      (Some(&Source::CommandLine), _) => true,
+      (Some(&Source::Env(_)), Some(&Source::Env(_))) => true,
+      (Some(source), Some(backend)) => source == backend,
+      (Some(_), None) => false,
+    };
+    let url = if applies("url") { self.url.as_ref() } else { None };
+    let endpoints = if applies("endpoints") { &self.endpoints[..] } else { &[] };
+    join_endpoints(url, endpoints)
+  }
+
+  /// Expands a compiler alias; other names are returned as they are.
+  pub fn expand_compiler(&self, name: &str) -> CompilerAlias {
+    match self.compiler_aliases.get(name) {
+      Some(&Either::Left(ref compiler)) => {
+        CompilerAlias {
+          compiler: compiler.clone(),
+          ..CompilerAlias::default()
+        }
+      }
+      Some(&Either::Right(ref alias)) => alias.clone(),
+      None => {
+        CompilerAlias {
+          compiler: name.to_owned(),
+          ..CompilerAlias::default()
+        }
+      }
+    }
+  }
+
+  /// Returns the values under a dotted key, e.g. `languages.C++`.
+  pub fn get(&self, key: &str) -> ::Result<Vec<&Entry>> {
+    split_key(key)?;
+    let prefix = format!("{}.", key);
+    Ok(self.entries.iter().filter(|e| e.key == key || e.key.starts_with(&prefix)).collect())
+  }
+}
+
+fn join_endpoints(url: Option<&String>, endpoints: &[String]) -> Vec<String> {
+  let mut joined = Vec::new();
+  for url in url.into_iter().chain(endpoints) {
+    let url = url.trim_right_matches('/').to_owned();
+    if !joined.contains(&url) {
+      joined.push(url);
+    }
+  }
+  joined
+}
+
+/// Returns the default path of the user configuration, which may not exist.
+pub fn user_config_file() -> ::Result<PathBuf> {
+  user_config_file_in(env::var("XDG_CONFIG_HOME").ok().as_ref().map(String::as_str))
+}
+
+fn user_config_file_in(config_home: Option<&str>) -> ::Result<PathBuf> {
+  let dir = match config_home {
+    Some(dir) if !dir.is_empty() => Path::new(dir).join("wan"),
+    _ => PathBuf::from(shellexpand::full(CONFIG_DIR)?.borrow() as &str),
+  };
+  Ok(dir.join("config.json"))
+}
+
+/// Finds `.wan.json` or `.wan.toml` in the current directory or its ancestors.
+pub fn find_project_config() -> ::Result<Option<PathBuf>> {
+  Ok(find_project_config_from(env::current_dir()?))
+}
+
+fn find_project_config_from(mut dir: PathBuf) -> Option<PathBuf> {
+  loop {
+    let mut paths = PROJECT_CONFIG_NAMES.iter().map(|name| dir.join(name)).filter(|path| path.is_file());
+    if let Some(path) = paths.next() {
+      for other in paths {
+        warn!("{} is ignored, since {} is used", other.display(), path.display());
+      }
+      return Some(path);
+    }
+    if !dir.pop() {
+      return None;
+    }
+  }
+}
+
+fn read_layer(path: &Path) -> ::Result<Value> {
+  let text = read_text(path)?;
+  let format = Format::of(path);
+  let problems = validate(&text, format);
+  if !problems.is_empty() {
+    bail!(::ErrorKind::InvalidConfig(path.display().to_string(),
+                                     util::str_join(problems.iter().map(|p| p.to_string()), "\n  ")));
+  }
+  format.parse(&text)
+}
+
+fn read_text(path: &Path) -> ::Result<String> {
+  let mut text = String::new();
+  File::open(path)
+    .and_then(|mut f| f.read_to_string(&mut text))
+    .chain_err(|| format!("failed to read {}", path.display()))?;
+  Ok(text)
+}
+
+/// Format of a configuration file, given by its extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+  Json,
+  Toml,
+}
+
+impl Format {
+  pub fn of(path: &Path) -> Format {
+    match path.extension() {
+      Some(ext) if ext == "toml" => Format::Toml,
+      _ => Format::Json,
+    }
+  }
+
+  fn parse(self, text: &str) -> ::Result<Value> {
+    match self {
+      Format::Json => Ok(serde_json::from_str(text)?),
+      Format::Toml => util::parse_toml(text),
+    }
+  }
+
+  fn serialize(self, value: &Value) -> ::Result<String> {
+    match self {
+      Format::Json => Ok(serde_json::to_string_pretty(value)? + "\n"),
+      Format::Toml => util::to_toml(value),
+    }
+  }
+}
+
+
+/// Expected type of a value in the configuration.
+enum Kind {
+  Str,
+  Num,
+  StrList,
+  /// A string, or a value of the kind.
+  StrOr(&'static Kind),
+  Struct(&'static [(&'static str, &'static Kind)]),
+  /// Object with arbitrary keys, e.g. names of languages.
+  Map(&'static Kind),
+  /// Any JSON value, which is checked when it is used.
+  Json,
+}
+
+static TLS: Kind = Kind::Struct(&[("ca-file", &Kind::Str), ("identity", &Kind::Str), ("identity-password", &Kind::Str)]);
+static AUTH: Kind = Kind::Struct(&[("token", &Kind::Str), ("username", &Kind::Str), ("password", &Kind::Str)]);
+static LANGUAGE: Kind = Kind::Struct(&[("compiler", &Kind::Str), ("extensions", &Kind::StrList)]);
+static COMPILER_ALIAS: Kind = Kind::Struct(&[("compiler", &Kind::Str),
+                                             ("options", &Kind::Str),
+                                             ("compile-args", &Kind::Str),
+                                             ("runtime-args", &Kind::Str)]);
+static PRESET: Kind = Kind::Struct(&[("description", &Kind::Str),
+                                     ("compilers", &Kind::StrList),
+                                     ("options", &Kind::Str),
+                                     ("compile-args", &Kind::Str),
+                                     ("runtime-args", &Kind::Str)]);
+static LOCAL_COMPILER: Kind = Kind::Struct(&[("language", &Kind::Str),
+                                             ("display-name", &Kind::Str),
+                                             ("version", &Kind::Str),
+                                             ("source", &Kind::Str),
+                                             ("compile", &Kind::Str),
+                                             ("run", &Kind::Str),
+                                             ("switches", &Kind::Json)]);
+static LOCAL: Kind = Kind::Struct(&[("timeout", &Kind::Num),
+                                    ("output-limit", &Kind::Num),
+                                    ("cpu-time", &Kind::Num),
+                                    ("memory-limit", &Kind::Num),
+                                    ("process-limit", &Kind::Num),
+                                    ("compilers", &Kind::Map(&LOCAL_COMPILER))]);
+static PROFILE: Kind = Kind::Struct(&[("url", &Kind::Str),
+                                      ("endpoints", &Kind::StrList),
+                                      ("languages", &Kind::Map(&LANGUAGE)),
+                                      ("options", &Kind::Map(&Kind::Str)),
+                                      ("tls", &TLS),
+                                      ("auth", &AUTH),
+                                      ("aliases", &Kind::Map(&Kind::Str)),
+                                      ("compiler-aliases", &Kind::Map(&Kind::StrOr(&COMPILER_ALIAS))),
+                                      ("presets", &Kind::Map(&PRESET)),
+                                      ("backend", &Kind::Str),
+                                      ("local", &LOCAL)]);
+static CONFIG: Kind = Kind::Struct(&[("url", &Kind::Str),
+                                     ("endpoints", &Kind::StrList),
+                                     ("languages", &Kind::Map(&LANGUAGE)),
+                                     ("options", &Kind::Map(&Kind::Str)),
+                                     ("tls", &TLS),
+                                     ("auth", &AUTH),
+                                     ("aliases", &Kind::Map(&Kind::Str)),
+                                     ("compiler-aliases", &Kind::Map(&Kind::StrOr(&COMPILER_ALIAS))),
+                                     ("presets", &Kind::Map(&PRESET)),
+                                     ("backend", &Kind::Str),
+                                     ("local", &LOCAL),
+                                     ("profile", &Kind::Str),
+                                     ("profiles", &Kind::Map(&PROFILE))]);
+
+/// An error found by `validate`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+  pub line: Option<usize>,
+  pub message: String,
+}
+
+impl fmt::Display for Problem {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.line {
+      Some(line) => write!(f, "line {}: {}", line, self.message),
+      None => write!(f, "{}", self.message),
+    }
+  }
+}
+
+/// Checks the content of a configuration file, and returns the problems found.
+pub fn validate(text: &str, format: Format) -> Vec<Problem> {
+  let value = match format.parse(text) {
+    Ok(value) => value,
+    Err(err) => {
+      return vec![Problem {
+                    line: None,
+                    message: err.to_string(),
+                  }]
+    }
+  };
+
+  let text = (text, format);
+  let mut problems = Vec::new();
+  check_value(&CONFIG, &value, &mut Vec::new(), text, &mut problems);
+  if let Some(profile) = value.get("profile").and_then(Value::as_str) {
+    if value.get("profiles").and_then(|p| p.get(profile)).is_none() {
+      problems.push(Problem {
+                      line: line_of(text, &["profile"]),
+                      message: format!("profile `{}` is not defined in `profiles`", profile),
+                    });
+    }
+  }
+  problems
+}
+
+fn check_value<'a>(kind: &Kind,
+                   value: &'a Value,
+                   path: &mut Vec<&'a str>,
+                   text: (&str, Format),
+                   problems: &mut Vec<Problem>) {
+  let expected = match (kind, value) {
+    (&Kind::Str, &Value::String(_)) |
+    (&Kind::Num, &Value::Number(_)) |
+    (&Kind::StrOr(_), &Value::String(_)) |
+    (&Kind::Json, _) => return,
+    (&Kind::StrOr(kind), &Value::Object(_)) => return check_value(kind, value, path, text, problems),
+    (&Kind::StrList, &Value::Array(ref values)) if values.iter().all(Value::is_string) => return,
+    (&Kind::Struct(fields), &Value::Object(ref map)) => {
+      for (key, value) in map {
+        path.push(key);
+        match fields.iter().find(|&&(name, _)| name == key) {
+          Some(&(_, kind)) => check_value(kind, value, path, text, problems),
+          None => {
+            let mut message = format!("unknown key `{}`", util::str_join(path.iter(), "."));
+            if let Some(name) = util::suggest(key, fields.iter().map(|&(name, _)| name)) {
+              message.push_str(&format!("; did you mean `{}`?", name));
+            }
+            problems.push(Problem {
+                            line: line_of(text, path),
+                            message: message,
+                          });
+          }
+        }
+        path.pop();
+      }
+      return;
+    }
+    (&Kind::Map(kind), &Value::Object(ref map)) => {
+      for (key, value) in map {
+        path.push(key);
+        check_value(kind, value, path, text, problems);
+        path.pop();
+      }
+      return;
+    }
+    (&Kind::Str, _) => "a string",
+    (&Kind::Num, _) => "a number",
+    (&Kind::StrList, _) => "an array of strings",
+    (&Kind::StrOr(_), _) => "a string or an object",
+    _ => "an object",
+  };
+  problems.push(Problem {
+                  line: line_of(text, path),
+                  message: if path.is_empty() {
+                    format!("configuration must be {}", expected)
+                  } else {
+                    format!("`{}` must be {}", util::str_join(path.iter(), "."), expected)
+                  },
+                });
+}
+
+/// Finds the line of a key by searching the names along the path in order.
+///
+/// Names are searched with quotes in JSON, and as they are in TOML where keys are usually bare.
+fn line_of<S: AsRef<str>>((text, format): (&str, Format), path: &[S]) -> Option<usize> {
+  let mut pos = 0;
+  for name in path {
+    let name = match format {
+      Format::Json => serde_json::to_string(name.as_ref()).unwrap(),
+      Format::Toml => name.as_ref().to_owned(),
+    };
+    pos += match text[pos..].find(&name) {
+      Some(i) => i + name.len(),
+      None => return None,
+    };
+  }
+  Some(text[..pos].matches('\n').count() + 1)
+}
+
+/// Returns the key set by an environment variable, e.g. `local.output-limit` by
+/// `WAN_LOCAL_OUTPUT_LIMIT`, with the type of its value.
+///
+/// Only strings, numbers and lists of strings can be set, and keys under objects with arbitrary
+/// names (e.g. `languages`) have no environment variable.
+fn env_key(name: &str) -> Option<(String, &'static Kind)> {
+  if !name.starts_with("WAN_") {
+    return None;
+  }
+  let mut rest = &name[4..];
+  let mut kind = &CONFIG;
+  let mut names = Vec::new();
+  loop {
+    let fields = match *kind {
+      Kind::Struct(fields) => fields,
+      _ => return None,
+    };
+    let mut parent = None;
+    for &(field, k) in fields {
+      let upper = field.to_uppercase().replace('-', "_");
+      if rest == upper {
+        names.push(field);
+        return match *k {
+                 Kind::Str | Kind::Num | Kind::StrList => Some((util::str_join(names, "."), k)),
+                 _ => None,
+               };
+      }
+      if rest.starts_with(&upper) && rest[upper.len()..].starts_with('_') {
+        parent = Some((field, k, upper.len() + 1));
+      }
+    }
+    match parent {
+      Some((field, k, len)) => {
+        names.push(field);
+        kind = k;
+        rest = &rest[len..];
+      }
+      None => return None,
+    }
+  }
+}
+
+/// Parses a value given as text, as a string, a comma-separated list or JSON by the type of the key.
+fn parse_value(key: &str, kind: &Kind, value: &str) -> ::Result<Value> {
+  Ok(match *kind {
+       Kind::Str => Value::String(value.to_owned()),
+       Kind::StrOr(_) if !value.trim().starts_with('{') => Value::String(value.to_owned()),
+       Kind::StrList if !value.trim().starts_with('[') => {
+         Value::Array(value.split(',')
+                           .map(str::trim)
+                           .filter(|s| !s.is_empty())
+                           .map(|s| Value::String(s.to_owned()))
+                           .collect())
+       }
+       _ => serde_json::from_str(value).chain_err(|| format!("value of `{}` must be JSON", key))?,
+     })
+}
+
+/// Splits a dotted key into the names along the path, and returns the type of its value.
+///
+/// Keys of objects such as `options` may contain dots, e.g. `options.gcc-4.9.2`.
+fn split_key(key: &str) -> ::Result<(Vec<&str>, &'static Kind)> {
+  let mut kind = &CONFIG;
+  let mut names = Vec::new();
+  let mut rest = key;
+  while !rest.is_empty() {
+    if let Kind::StrOr(inner) = *kind {
+      kind = inner;
+    }
+    let len = match *kind {
+      Kind::Struct(fields) => {
+        let name = rest.split('.').next().unwrap();
+        match fields.iter().find(|&&(n, _)| n == name) {
+          Some(&(_, k)) => kind = k,
+          None => {
+            let mut message = format!("unknown key `{}`", key);
+            if let Some(n) = util::suggest(name, fields.iter().map(|&(n, _)| n)) {
+              message.push_str(&format!("; did you mean `{}`?", n));
+            }
+            bail!(message);
+          }
+        }
+        name.len()
+      }
+      Kind::Map(inner) => {
+        kind = inner;
+        match *inner {
+          Kind::Struct(fields) |
+          Kind::StrOr(&Kind::Struct(fields)) => {
+            rest.match_indices('.')
+                .map(|(i, _)| i)
+                .find(|&i| fields.iter().any(|&(n, _)| rest[i + 1..].split('.').next() == Some(n)))
+                .unwrap_or(rest.len())
+          }
+          _ => rest.len(),
+        }
+      }
+      _ => bail!("unknown key `{}`; `{}` is not an object", key, util::str_join(names.iter(), ".")),
+    };
+    if len == 0 {
+      bail!("invalid key `{}`", key);
+    }
+    names.push(&rest[..len]);
+    rest = if len < rest.len() { &rest[len + 1..] } else { "" };
+  }
+  if names.is_empty() {
+    bail!("key must not be empty");
+  }
+  Ok((names, kind))
+}
+
+
+/// A configuration file, edited by `wan config`.
+#[derive(Debug)]
+pub struct ConfigFile {
+  path: PathBuf,
+  value: Value,
+}
+
+impl ConfigFile {
+  /// Opens a configuration file, which is created on `save` if it does not exist.
+  pub fn open<P: Into<PathBuf>>(path: P) -> ::Result<ConfigFile> {
+    let path = path.into();
+    let value = if path.exists() {
+      let value = Format::of(&path)
+        .parse(&read_text(&path)?)
+        .chain_err(|| format!("failed to parse {}", path.display()))?;
+      if !value.is_object() {
+        bail!("{} must contain a JSON object", path.display());
+      }
+      value
+    } else {
+      Value::Object(Map::new())
+    };
+    Ok(ConfigFile {
+         path: path,
+         value: value,
+       })
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  pub fn get(&self, key: &str) -> ::Result<Option<&Value>> {
+    let (names, _) = split_key(key)?;
+    Ok(names.iter().fold(Some(&self.value), |value, name| value.and_then(|v| v.get(*name))))
+  }
+
+  /// Sets the value of a dotted key.
+  ///
+  /// The value is taken as a string, a comma-separated list or JSON, by the type of the key.
+  pub fn set(&mut self, key: &str, value: &str) -> ::Result<()> {
+    let (names, kind) = split_key(key)?;
+    let value = parse_value(key, kind, value)?;
+
+    let (last, parents) = names.split_last().unwrap();
+    let mut target = &mut self.value;
+    for name in parents {
+      let map = match *{ target } {
+        Value::Object(ref mut map) => map,
+        _ => bail!("cannot set `{}`; its parent is not an object", key),
+      };
+      target = map.entry(name.to_string()).or_insert_with(|| Value::Object(Map::new()));
+    }
+    match *target {
+      Value::Object(ref mut map) => {
+        map.insert(last.to_string(), value);
+      }
+      _ => bail!("cannot set `{}`; its parent is not an object", key),
+    }
+    Ok(())
+  }
+
+  /// Removes a key, along with the objects left empty.
+  pub fn unset(&mut self, key: &str) -> ::Result<()> {
+    let (names, _) = split_key(key)?;
+    if !remove_key(&mut self.value, &names) {
+      bail!("`{}` is not set in {}", key, self.path.display());
+    }
+    Ok(())
+  }
+
+  /// Validates the configuration and writes it to the file.
+  pub fn save(&self) -> ::Result<()> {
+    let format = Format::of(&self.path);
+    let text = format.serialize(&self.value)?;
+    let problems = validate(&text, format);
+    if !problems.is_empty() {
+      bail!("invalid configuration:\n  {}",
+            util::str_join(problems.iter().map(|p| p.to_string()), "\n  "));
+    }
+    if let Some(dir) = self.path.parent() {
+      if !dir.as_os_str().is_empty() {
+        fs::create_dir_all(dir)?;
+      }
+    }
+    File::create(&self.path)
+      .and_then(|mut f| f.write_all(text.as_bytes()))
+      .chain_err(|| format!("failed to write {}", self.path.display()))?;
+    Ok(())
+  }
+}
+
+/// Checks a configuration file, and returns the problems found.
+pub fn validate_file<P: AsRef<Path>>(path: P) -> ::Result<Vec<Problem>> {
+  let path = path.as_ref();
+  Ok(validate(&read_text(path)?, Format::of(path)))
+}
+
+fn remove_key(value: &mut Value, names: &[&str]) -> bool {
+  let map = match *value {
+    Value::Object(ref mut map) => map,
+    _ => return false,
+  };
+  if names.len() == 1 {
+    return map.remove(names[0]).is_some();
+  }
+  let removed = match map.get_mut(names[0]) {
+    Some(child) => remove_key(child, &names[1..]),
+    None => false,
+  };
+  if removed && map[names[0]].as_object().map_or(false, |m| m.is_empty()) {
+    map.remove(names[0]);
+  }
+  removed
+}
+
+
+/// Accumulates the layers of the configuration.
+#[derive(Debug)]
+struct Layers {
+  value: Value,
+  entries: Vec<Entry>,
+}
+
+impl Default for Layers {
+  fn default() -> Layers {
+    Layers {
+      value: Value::Object(Map::new()),
+      entries: Vec::new(),
+    }
+  }
+}
+
+impl Layers {
+  /// Merges a layer; objects are merged recursively and other values are replaced.
+  fn merge(&mut self, layer: Value, source: Source) {
+    let mut leaves = BTreeMap::new();
+    flatten("", &layer, &mut leaves);
+    merge_value(&mut self.value, layer);
+    for (key, value) in leaves {
+      self.record(key, value, source.clone());
+    }
+  }
+
+  /// Sets the value of a dotted key.
+  fn set(&mut self, key: &str, value: Value, source: Source) {
+    let mut layer = value.clone();
+    for part in key.rsplit('.') {
+      let mut map = Map::new();
+      map.insert(part.to_owned(), layer);
+      layer = Value::Object(map);
+    }
+    merge_value(&mut self.value, layer);
+    self.record(key.to_owned(), value, source);
+  }
+
+  /// Overlays the profile of the given name.
+  fn apply_profile(&mut self, name: String, source: Source) -> ::Result<()> {
+    let overlay = match self.value.get("profiles").and_then(|profiles| profiles.get(name.as_str())) {
+      Some(overlay) => overlay.clone(),
+      None => {
+        let available = self.value
+                            .get("profiles")
+                            .and_then(Value::as_object)
+                            .map(|profiles| util::str_join(profiles.keys(), ", "))
+                            .unwrap_or_default();
+        bail!("profile `{}` is not defined (available: {})", name, available);
+      }
+    };
+    if !overlay.is_object() {
+      bail!("profile `{}` must be a JSON object", name);
+    }
+    self.set("profile", Value::String(name.clone()), source);
+    self.merge(overlay, Source::Profile(name));
+    Ok(())
+  }
+
+  /// Returns the string value of a key, with its origin.
+  fn source_of(&self, key: &str) -> Option<(String, Source)> {
+    self.entries
+        .iter()
+        .find(|e| e.key == key)
+        .and_then(|e| e.value.as_str().map(|value| (value.to_owned(), e.source.clone())))
+  }
+
+  fn record(&mut self, key: String, value: Value, source: Source) {
+    // A value replaces the values of the same key and of the keys nested in it.
+    let prefix = format!("{}.", key);
+    self.entries.retain(|e| e.key != key && !e.key.starts_with(&prefix) && !key.starts_with(&format!("{}.", e.key)));
+    self.entries.push(Entry {
+      key: key,
+      value: value,
+      source: source,
+    });
+    self.entries.sort_by(|a, b| a.key.cmp(&b.key));
+  }
+}
+
+fn merge_value(base: &mut Value, layer: Value) {
+  match (base, layer) {
+    (&mut Value::Object(ref mut base), Value::Object(layer)) => {
+      for (key, value) in layer {
+        if base.contains_key(&key) {
+          merge_value(base.get_mut(&key).unwrap(), value);
+        } else {
+          base.insert(key, value);
+        }
+      }
+    }
+    (base, layer) => *base = layer,
+  }
+}
+
+fn flatten(prefix: &str, value: &Value, leaves: &mut BTreeMap<String, Value>) {
+  match *value {
+    Value::Object(ref map) if !map.is_empty() || prefix.is_empty() => {
+      for (key, value) in map {
+        let key = if prefix.is_empty() {
+          key.clone()
+        } else {
+          format!("{}.{}", prefix, key)
+        };
+        flatten(&key, value, leaves);
+      }
+    }
+    _ => {
+      leaves.insert(prefix.to_owned(), value.clone());
+    }
+  }
+}
+
+
+#[test]
+fn test_layers() {
+  let mut layers = Layers::default();
+  layers.merge(serde_json::from_str(r#"{ "url": "a" }"#).unwrap(), Source::Default);
+  layers.merge(serde_json::from_str(r#"{
+      "url": "b",
+      "languages": { "C++": { "compiler": "clang-head", "extensions": ["cpp"] } }
+    }"#)
+                 .unwrap(),
+               Source::File(PathBuf::from("config.json")));
+  layers.merge(serde_json::from_str(r#"{ "languages": { "C++": { "compiler": "gcc-head" } } }"#).unwrap(),
+               Source::File(PathBuf::from(".wan.json")));
+  layers.set("url", Value::String("c".to_owned()), Source::Env("WAN_URL".to_owned()));
+
+  let keys: Vec<_> = layers.entries.iter().map(|e| (e.key.as_str(), e.source.to_string())).collect();
+  assert_eq!(keys,
+             [("languages.C++.compiler", ".wan.json".to_owned()),
+              ("languages.C++.extensions", "config.json".to_owned()),
+              ("url", "environment variable WAN_URL".to_owned())]);
+
+  let config: Config = serde_json::from_value(layers.value).unwrap();
+  assert_eq!(config.url, Some("c".to_owned()));
+  assert!(config.profiles.is_empty());
+  assert_eq!(config.languages["C++"].compiler, Some("gcc-head".to_owned()));
+  assert_eq!(config.languages["C++"].extensions, Some(vec!["cpp".to_owned()]));
+}
+
+#[test]
+fn test_layers_profile() {
+  let mut layers = Layers::default();
+  layers.merge(serde_json::from_str(r#"{
+      "url": "https://wandbox.org",
+      "profile": "local",
+      "profiles": {
+        "local": { "url": "http://127.0.0.1:3500", "options": { "gcc-head": "warning" } },
+        "staging": { "url": "https://staging", "auth": { "token": "secret" } }
+      }
+    }"#)
+                 .unwrap(),
+               Source::File(PathBuf::from("config.json")));
+  assert!(layers.apply_profile("production".to_owned(), Source::CommandLine).is_err());
+
+  let (name, source) = layers.source_of("profile").unwrap();
+  layers.apply_profile(name, source).unwrap();
+  assert_eq!(layers.source_of("url"),
+             Some(("http://127.0.0.1:3500".to_owned(), Source::Profile("local".to_owned()))));
+
+  let config: Config = serde_json::from_value(layers.value).unwrap();
+  assert_eq!(config.options["gcc-head"], "warning");
+  assert!(config.auth.is_none());
+  assert_eq!(config.profiles["staging"].auth.as_ref().unwrap().token, Some("secret".to_owned()));
+}
+
+#[test]
+fn test_validate() {
+  let text = r#"{
+  "url": "https://wandbox.org",
+  "languges": {},
+  "languages": {
+    "C++": { "compiler": "clang-head", "extensions": "cpp" }
+  },
+  "profiles": {
+    "local": { "tls": { "ca_file": "ca.der" } }
+  }
+}"#;
+  let problems: Vec<_> = validate(text, Format::Json).into_iter().map(|p| p.to_string()).collect();
+  assert_eq!(problems,
+             ["line 5: `languages.C++.extensions` must be an array of strings",
+              "line 3: unknown key `languges`; did you mean `languages`?",
+              "line 8: unknown key `profiles.local.tls.ca_file`; did you mean `ca-file`?"]);
+
+  assert!(validate(r#"{ "url": "a", }"#, Format::Json)[0].message.contains("line 1"));
+  assert_eq!(validate(r#"{ "profile": "local" }"#, Format::Json).len(), 1);
+  assert!(validate(r#"{ "profile": "local", "profiles": { "local": {} } }"#, Format::Json).is_empty());
+
+  let text = r#"url = "https://wandbox.org"
+
+[languages."C++"]
+compiler = "clang-head"
+extensions = "cpp"
+"#;
+  let problems: Vec<_> = validate(text, Format::Toml).into_iter().map(|p| p.to_string()).collect();
+  assert_eq!(problems, ["line 5: `languages.C++.extensions` must be an array of strings"]);
+  assert_eq!(validate("url = ", Format::Toml).len(), 1);
+}
+
+#[test]
+fn test_config_file() {
+  let mut file = ConfigFile {
+    path: PathBuf::from("config.json"),
+    value: Value::Object(Map::new()),
+  };
+  file.set("url", "http://127.0.0.1:3500").unwrap();
+  file.set("options.gcc-4.9.2", "warning").unwrap();
+  file.set("profiles.local.languages.C++.extensions", "cpp, h").unwrap();
+  file.set("profiles.local.tls", r#"{ "ca-file": "ca.der" }"#).unwrap();
+  file.set("compiler-aliases.g", "gcc-head").unwrap();
+  file.set("compiler-aliases.cl17.compiler", "clang-head").unwrap();
+  assert!(file.set("compiler-aliases.g.options", "warning").is_err());
+  assert!(file.set("urls", "a").unwrap_err().to_string().contains("did you mean `url`?"));
+  assert!(file.set("url.path", "a").is_err());
+
+  assert_eq!(file.get("options.gcc-4.9.2").unwrap(), Some(&Value::String("warning".to_owned())));
+  assert_eq!(file.get("profiles.local.languages.C++.extensions").unwrap().unwrap().as_array().unwrap().len(),
+             2);
+
+  file.unset("profiles.local.languages.C++.extensions").unwrap();
+  assert!(file.get("profiles.local.languages").unwrap().is_none());
+  assert!(file.get("profiles.local.tls.ca-file").unwrap().is_some());
+  assert!(file.unset("auth.token").is_err());
+}
+
+#[test]
+fn test_compiler_aliases() {
+  let config: Config = serde_json::from_str(r#"{
+    "compiler-aliases": {
+      "g": "gcc-head",
+      "cl17": { "compiler": "clang-head", "options": "c++17,warning" }
+    }
+  }"#)
+    .unwrap();
+  assert_eq!(config.expand_compiler("g").compiler, "gcc-head");
+  assert_eq!(config.expand_compiler("cl17").options, Some("c++17,warning".to_owned()));
+  assert_eq!(config.expand_compiler("gcc-head"),
+             CompilerAlias {
+               compiler: "gcc-head".to_owned(),
+               ..CompilerAlias::default()
+             });
+
+  assert!(validate(r#"{ "compiler-aliases": { "g": ["gcc-head"] } }"#, Format::Json)[0]
+            .message
+            .contains("must be a string or an object"));
+  assert!(validate(r#"{ "compiler-aliases": { "g": { "compilr": "gcc-head" } } }"#, Format::Json)[0]
+            .message
+            .contains("did you mean `compiler`?"));
+}
+
+/// Loads the configuration with the text as the user configuration, and the environment variables
+/// instead of those of the process.
+///
+/// An empty `.wan.json` is placed next to it, so that no project configuration above is used.
+#[cfg(test)]
+fn load_text(text: &str, env: &[(&str, &str)]) -> ::Result<Config> {
+  let dir = util::TempDir::new()?;
+  let path = dir.path().join("config.json");
+  File::create(&path).and_then(|mut f| f.write_all(text.as_bytes()))?;
+  File::create(dir.path().join(".wan.json")).and_then(|mut f| f.write_all(b"{}"))?;
+  Config::load_with(&LoadOptions {
+                      path: Some(path),
+                      dir: Some(dir.path().to_owned()),
+                      env: Some(env.iter().map(|&(k, v)| (k.to_owned(), v.to_owned())).collect()),
+                      ..LoadOptions::default()
+                    })
+}
+
+#[test]
+fn test_endpoints() {
+  let config = load_text(r#"{ "endpoints": ["http://127.0.0.1:3500/", "https://wandbox.org"] }"#, &[]).unwrap();
+  assert_eq!(config.endpoints(), ["http://127.0.0.1:3500", "https://wandbox.org"]);
+
+  let config = load_text(r#"{
+    "url": "http://127.0.0.1:3500/wandbox/",
+    "endpoints": ["https://wandbox.org", "http://127.0.0.1:3500/wandbox"]
+  }"#,
+                         &[])
+    .unwrap();
+  assert_eq!(config.endpoints(), ["http://127.0.0.1:3500/wandbox", "https://wandbox.org"]);
+
+  let config = load_text("{}", &[]).unwrap();
+  assert!(config.endpoints().is_empty());
+  assert_eq!(config.backend, Some("wandbox".to_owned()));
+
+  // The URLs of Wandbox are not used by the backend selected in a profile.
+  let text = r#"{
+    "url": "http://127.0.0.1:3500",
+    "profile": "godbolt",
+    "profiles": {
+      "godbolt": { "backend": "godbolt" },
+      "mirror": { "backend": "godbolt", "endpoints": ["http://127.0.0.1:10240"] }
+    }
+  }"#;
+  let config = load_text(text, &[("WAN_ENDPOINTS", "http://127.0.0.1:3501")]).unwrap();
+  assert_eq!(config.endpoints(), ["http://127.0.0.1:3500", "http://127.0.0.1:3501"]);
+  assert_eq!(config.endpoints_for("wandbox"), config.endpoints());
+  assert!(config.endpoints_for("godbolt").is_empty());
+  let config = load_text(text, &[("WAN_PROFILE", "mirror")]).unwrap();
+  assert_eq!(config.endpoints_for("godbolt"), ["http://127.0.0.1:10240"]);
+  let config = load_text(text, &[("WAN_BACKEND", "godbolt"), ("WAN_URL", "http://127.0.0.1:10240")]).unwrap();
+  assert_eq!(config.endpoints_for("godbolt"), ["http://127.0.0.1:10240"]);
+}
+
+#[test]
+fn test_env() {
+  assert_eq!(env_key("WAN_URL").map(|(key, _)| key), Some("url".to_owned()));
+  assert_eq!(env_key("WAN_LOCAL_OUTPUT_LIMIT").map(|(key, _)| key), Some("local.output-limit".to_owned()));
+  assert_eq!(env_key("WAN_TLS_IDENTITY_PASSWORD").map(|(key, _)| key), Some("tls.identity-password".to_owned()));
+  assert!(env_key("WAN_LOCAL").is_none());
+  assert!(env_key("WAN_LANGUAGES_CPP_COMPILER").is_none());
+  assert!(env_key("WAN_BACKTRACE").is_none());
+
+  let config = load_text(r#"{ "profiles": { "local": { "url": "http://127.0.0.1:3500" } } }"#,
+                         &[("WAN_PROFILE", "local"),
+                           ("WAN_BACKEND", "local"),
+                           ("WAN_ENDPOINTS", "https://wandbox.org, http://127.0.0.1:3501"),
+                           ("WAN_LOCAL_TIMEOUT", "5"),
+                           ("WAN_BACKTRACE", "1")])
+    .unwrap();
+  assert_eq!(config.url, Some("http://127.0.0.1:3500".to_owned()));
+  assert_eq!(config.backend, Some("local".to_owned()));
+  assert_eq!(config.endpoints, ["https://wandbox.org", "http://127.0.0.1:3501"]);
+  assert_eq!(config.local.timeout, Some(5));
+  assert_eq!(config.get("local.timeout").unwrap()[0].source, Source::Env("WAN_LOCAL_TIMEOUT".to_owned()));
+
+  assert!(load_text("{}", &[("WAN_LOCAL_TIMEOUT", "soon")]).is_err());
+}
+
+#[test]
+fn test_project_config() {
+  let dir = util::TempDir::new().unwrap();
+  let sub = dir.path().join("src");
+  fs::create_dir(&sub).unwrap();
+  File::create(dir.path().join(".wan.toml"))
+    .and_then(|mut f| f.write_all(b"backend = \"godbolt\"\n[options]\ng132 = \"asm\"\n"))
+    .unwrap();
+  let mut env = HashMap::new();
+  env.insert("XDG_CONFIG_HOME".to_owned(), dir.path().to_string_lossy().into_owned());
+  let config = Config::load_with(&LoadOptions {
+                                   dir: Some(sub),
+                                   env: Some(env),
+                                   ..LoadOptions::default()
+                                 })
+    .unwrap();
+  assert_eq!(config.backend, Some("godbolt".to_owned()));
+  assert_eq!(config.options["g132"], "asm");
+
+  let mut file = ConfigFile::open(dir.path().join(".wan.toml")).unwrap();
+  file.set("local.timeout", "5").unwrap();
+  file.save().unwrap();
+  assert_eq!(ConfigFile::open(dir.path().join(".wan.toml")).unwrap().get("local.timeout").unwrap(),
+             Some(&Value::from(5)));
 }
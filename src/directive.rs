@@ -0,0 +1,121 @@
+use shlex;
+
+/// Comment markers tried when the language of a source is unknown.
+const DEFAULT_COMMENTS: &'static [&'static str] = &["//", "#", "--", ";", "%", "/*", "(*"];
+
+/// Settings embedded in directive comments at the top of a source, e.g.
+///
+/// ```text
+/// // wan: compiler=clang-head options=warning,c++17 runtime-args="a b"
+/// ```
+#[derive(Debug, Default, PartialEq)]
+pub struct Directives {
+  pub compiler: Option<String>,
+  pub options: Option<String>,
+  pub compiler_args: Option<String>,
+  pub runtime_args: Option<String>,
+  pub stdin: Option<String>,
+}
+
+impl Directives {
+  /// Parses the directives in the leading comments of `code`.
+  ///
+  /// Only the comment lines before the first line of code are examined.  When `comments` is
+  /// empty, common comment markers are tried.  Values of `options` in several directives are
+  /// joined, and other keys are overwritten by later directives.
+  pub fn parse<S: AsRef<str>>(code: &str, comments: &[S]) -> ::Result<Directives> {
+    let comments: Vec<&str> = if comments.is_empty() {
+      DEFAULT_COMMENTS.to_vec()
+    } else {
+      comments.iter().map(|s| s.as_ref()).collect()
+    };
+
+    let mut directives = Directives::default();
+    for (i, line) in code.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || (i == 0 && line.starts_with("#!")) {
+        continue;
+      }
+      let body = match comments.iter().find(|c| line.starts_with(**c)) {
+        Some(comment) => strip_comment_end(&line[comment.len()..]).trim(),
+        None => break,
+      };
+      if body.starts_with("wan:") {
+        directives.parse_line(&body["wan:".len()..])?;
+      }
+    }
+    Ok(directives)
+  }
+
+  fn parse_line(&mut self, line: &str) -> ::Result<()> {
+    let words = match shlex::split(line) {
+      Some(words) => words,
+      None => bail!("invalid quotation in directive: {}", line.trim()),
+    };
+    for word in words {
+      let mut kv = word.splitn(2, '=');
+      let (key, value) = match (kv.next(), kv.next()) {
+        (Some(key), Some(value)) => (key, value.to_owned()),
+        _ => bail!("directive `{}` must be the form of `key=value`", word),
+      };
+      match key {
+        "compiler" => self.compiler = Some(value),
+        "options" => {
+          self.options = Some(match self.options.take() {
+                                Some(options) => format!("{},{}", options, value),
+                                None => value,
+                              })
+        }
+        "compile-args" | "compiler-args" => self.compiler_args = Some(value),
+        "runtime-args" => self.runtime_args = Some(value),
+        "stdin" => self.stdin = Some(value),
+        _ => bail!("unknown directive `{}` (expected compiler, options, compile-args, runtime-args or stdin)", key),
+      }
+    }
+    Ok(())
+  }
+}
+
+fn strip_comment_end(s: &str) -> &str {
+  for end in &["*/", "*)", "-}", "}"] {
+    if s.ends_with(end) {
+      return &s[..s.len() - end.len()];
+    }
+  }
+  s
+}
+
+
+#[test]
+fn test_parse_directives() {
+  let code = r#"#!/usr/bin/env wan
+// wan: compiler=clang-head options=warning,c++17
+/* wan: runtime-args="a b" options=optimize */
+
+// wan: compiler=gcc-head
+#include <iostream>
+// wan: stdin=ignored
+"#;
+  let directives = Directives::parse(code, &["//", "/*"]).unwrap();
+  assert_eq!(directives,
+             Directives {
+               compiler: Some("gcc-head".to_owned()),
+               options: Some("warning,c++17,optimize".to_owned()),
+               compiler_args: None,
+               runtime_args: Some("a b".to_owned()),
+               stdin: None,
+             });
+}
+
+#[test]
+fn test_parse_directives_comments() {
+  let code = "# wan: compiler=cpython-head\nprint(1)\n";
+  let directives = Directives::parse::<String>(code, &[]).unwrap();
+  assert_eq!(directives.compiler, Some("cpython-head".to_owned()));
+
+  let directives = Directives::parse(code, &["//"]).unwrap();
+  assert_eq!(directives, Directives::default());
+
+  assert!(Directives::parse(code.replace("compiler", "compilr").as_str(), &["#"]).is_err());
+  assert!(Directives::parse("# wan: options\n", &["#"]).is_err());
+}
@@ -0,0 +1,352 @@
+//! A backend which compiles and runs the code on Compiler Explorer (godbolt.org).
+//!
+//! Compilers are named by the ids of Compiler Explorer, e.g. `g132` or `clang1600`. With the
+//! `asm` option, the assembly is shown as the program output instead of running the program.
+
+use std::collections::HashMap;
+use std::io::{self, Read};
+
+use hyper::method::Method;
+use hyper::status::StatusCode;
+use serde_json;
+
+use backend::Backend;
+use config::Config;
+use http;
+use util::{self, Either};
+use wandbox::{CompilerInfo, CompilerSwitch, Parameter, Response};
+use ResultExt;
+
+pub const GODBOLT_URL: &'static str = "https://godbolt.org";
+
+/// Name of the option to show the assembly.
+const ASM_OPTION: &'static str = "asm";
+
+#[derive(Deserialize)]
+struct Language {
+  id: String,
+  name: String,
+}
+
+#[derive(Deserialize)]
+struct Compiler {
+  id: String,
+  name: String,
+  lang: String,
+  #[serde(default)]
+  semver: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CompileRequest<'a> {
+  source: &'a str,
+  options: CompileOptions<'a>,
+  files: Vec<File<'a>>,
+}
+
+#[derive(Serialize)]
+struct CompileOptions<'a> {
+  #[serde(rename = "userArguments")]
+  user_arguments: String,
+  #[serde(rename = "executeParameters")]
+  execute_parameters: ExecuteParameters<'a>,
+  #[serde(rename = "compilerOptions")]
+  compiler_options: CompilerOptions,
+  filters: Filters,
+}
+
+#[derive(Serialize)]
+struct ExecuteParameters<'a> {
+  args: Vec<String>,
+  stdin: &'a str,
+}
+
+#[derive(Serialize)]
+struct CompilerOptions {
+  #[serde(rename = "executorRequest")]
+  executor_request: bool,
+  #[serde(rename = "skipAsm")]
+  skip_asm: bool,
+}
+
+#[derive(Serialize)]
+struct Filters {
+  execute: bool,
+  intel: bool,
+  demangle: bool,
+  labels: bool,
+  directives: bool,
+  #[serde(rename = "commentOnly")]
+  comment_only: bool,
+}
+
+#[derive(Serialize)]
+struct File<'a> {
+  filename: &'a str,
+  contents: &'a str,
+}
+
+/// Result of a compilation, or of an execution which has the compilation in `buildResult`.
+#[derive(Debug, Default, Deserialize)]
+struct CompileResult {
+  #[serde(default)]
+  code: i32,
+  #[serde(default)]
+  stdout: Vec<Line>,
+  #[serde(default)]
+  stderr: Vec<Line>,
+  #[serde(default)]
+  asm: Vec<Line>,
+  #[serde(default, rename = "didExecute")]
+  did_execute: bool,
+  #[serde(default, rename = "buildResult")]
+  build_result: Option<Box<CompileResult>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Line {
+  #[serde(default)]
+  text: String,
+}
+
+pub struct Godbolt {
+  http: http::Client,
+}
+
+impl Godbolt {
+  pub fn new(url: Option<String>) -> Godbolt {
+    Godbolt { http: http::Client::new(vec![url.unwrap_or_else(|| GODBOLT_URL.to_owned())]) }
+  }
+
+  pub fn from_config(config: &Config) -> Godbolt {
+    Godbolt { http: http::Client::for_backend(config, "godbolt", GODBOLT_URL) }
+  }
+
+  /// Returns the compilers, with the names of their languages.
+  pub fn compilers(&self) -> ::Result<Vec<CompilerInfo>> {
+    let languages: Vec<Language> = self.get("/api/languages")?;
+    let languages: HashMap<_, _> = languages.into_iter().map(|l| (l.id, l.name)).collect();
+    let compilers: Vec<Compiler> = self.get("/api/compilers")?;
+    Ok(compilers.into_iter()
+                .map(|c| {
+                       CompilerInfo {
+                         version: c.semver.unwrap_or_default(),
+                         language: languages.get(&c.lang).cloned().unwrap_or(c.lang),
+                         display_name: c.name,
+                         compiler_option_raw: true,
+                         runtime_option_raw: true,
+                         display_compile_command: String::new(),
+                         switches: vec![Either::Left(CompilerSwitch {
+                                                       default: false,
+                                                       name: ASM_OPTION.to_owned(),
+                                                       display_name: "Show assembly instead of running".to_owned(),
+                                                       display_flags: String::new(),
+                                                     })],
+                         name: c.id,
+                       }
+                     })
+                .collect())
+  }
+
+  fn get<T: ::serde::Deserialize>(&self, path: &str) -> ::Result<T> {
+    let (status, body) = self.http.forward(Method::Get, path, None)?;
+    let url = format!("{}{}", self.http.url(), path);
+    if !status.is_success() {
+      bail!(::ErrorKind::HttpStatus(url, status.to_string()));
+    }
+    serde_json::from_str(&body).chain_err(|| format!("invalid response from {}", url))
+  }
+}
+
+impl Backend for Godbolt {
+  fn get_compiler_info_raw(&self) -> ::Result<Box<Read>> {
+    let json = serde_json::to_string(&self.compilers()?)?;
+    Ok(Box::new(io::Cursor::new(json.into_bytes())))
+  }
+
+  fn get_compiler_info(&self) -> ::Result<Vec<CompilerInfo>> {
+    self.compilers()
+  }
+
+  fn compile(&self, param: Parameter) -> ::Result<Response> {
+    if param.save == Some(true) {
+      warn!("permlinks are not supported by Compiler Explorer; the code is not saved");
+    }
+    let asm = param.options.as_ref().map_or(false, |options| options.split(',').any(|o| o == ASM_OPTION));
+    let request = to_request(&param, asm);
+    let body = serde_json::to_string(&request)?;
+
+    let path = format!("/api/compiler/{}/compile", param.compiler);
+    let url = format!("{}{}", self.http.url(), path);
+    let (status, body) = self.http.forward(Method::Post, &path, Some(&body))?;
+    if status == StatusCode::NotFound {
+      bail!(::ErrorKind::UnknownCompiler(param.compiler.clone()));
+    }
+    if !status.is_success() {
+      bail!(::ErrorKind::HttpStatus(url, status.to_string()));
+    }
+    let result: CompileResult = serde_json::from_str(&body).chain_err(|| format!("invalid response from {}", url))?;
+    Ok(to_response(result, asm))
+  }
+}
+
+fn to_request<'a>(param: &'a Parameter, asm: bool) -> CompileRequest<'a> {
+  CompileRequest {
+    source: &param.code,
+    options: CompileOptions {
+      user_arguments: util::shell_join(util::raw_args(&param.compiler_option_raw)),
+      execute_parameters: ExecuteParameters {
+        args: util::raw_args(&param.runtime_option_raw),
+        stdin: param.stdin.as_ref().map_or("", String::as_str),
+      },
+      compiler_options: CompilerOptions {
+        executor_request: !asm,
+        skip_asm: !asm,
+      },
+      filters: Filters {
+        execute: !asm,
+        intel: true,
+        demangle: true,
+        labels: true,
+        directives: true,
+        comment_only: true,
+      },
+    },
+    files: param.codes
+                .iter()
+                .flat_map(|codes| codes.iter())
+                .map(|code| {
+                       File {
+                         filename: &code.file,
+                         contents: &code.code,
+                       }
+                     })
+                .collect(),
+  }
+}
+
+fn to_response(result: CompileResult, asm: bool) -> Response {
+  let mut response = Response::default();
+
+  // Without execution, the result is of the compilation itself.
+  let build = match result.build_result {
+    Some(ref build) if !asm => build,
+    _ => &result,
+  };
+  let (stdout, stderr) = (join_lines(&build.stdout), join_lines(&build.stderr));
+  response.compiler_message = Some(format!("{}{}", stdout, stderr));
+  response.compiler_output = Some(stdout);
+  response.compiler_error = Some(stderr);
+  if build.code != 0 {
+    response.status = build.code;
+    return response;
+  }
+
+  if asm {
+    let asm = join_lines(&result.asm);
+    response.program_message = Some(asm.clone());
+    response.program_output = Some(asm);
+  } else if result.did_execute {
+    let (stdout, stderr) = (join_lines(&result.stdout), join_lines(&result.stderr));
+    response.status = result.code;
+    response.program_message = Some(format!("{}{}", stdout, stderr));
+    response.program_output = Some(stdout);
+    response.program_error = Some(stderr);
+  } else {
+    response.status = if result.code != 0 { result.code } else { 1 };
+  }
+  response
+}
+
+fn join_lines(lines: &[Line]) -> String {
+  lines.iter().map(|line| format!("{}\n", line.text)).collect()
+}
+
+
+#[test]
+fn test_from_config() {
+  use std::io::Write;
+  use config::LoadOptions;
+
+  // The top-level `url` and `WAN_URL` are of Wandbox, and the profile selecting the backend falls back to Compiler
+  // Explorer.
+  let text = r#"{
+    "url": "http://127.0.0.1:3500",
+    "profiles": {
+      "godbolt": { "backend": "godbolt" },
+      "mirror": { "backend": "godbolt", "url": "http://127.0.0.1:10240" }
+    }
+  }"#;
+  let dir = util::TempDir::new().unwrap();
+  ::std::fs::File::create(dir.path().join(".wan.json")).and_then(|mut f| f.write_all(text.as_bytes())).unwrap();
+  // Neither the environment nor the user configuration of the process is used.
+  let url = |env: &[(&str, &str)]| {
+    let mut env: HashMap<_, _> = env.iter().map(|&(k, v)| (k.to_owned(), v.to_owned())).collect();
+    env.insert("XDG_CONFIG_HOME".to_owned(), dir.path().display().to_string());
+    let config = Config::load_with(&LoadOptions {
+                                     dir: Some(dir.path().to_owned()),
+                                     env: Some(env),
+                                     ..LoadOptions::default()
+                                   })
+      .unwrap();
+    Godbolt::from_config(&config).http.url().to_owned()
+  };
+  assert_eq!(url(&[("WAN_PROFILE", "godbolt")]), GODBOLT_URL);
+  assert_eq!(url(&[("WAN_PROFILE", "godbolt"), ("WAN_URL", "http://127.0.0.1:3501")]), GODBOLT_URL);
+  assert_eq!(url(&[("WAN_PROFILE", "mirror")]), "http://127.0.0.1:10240");
+}
+
+#[test]
+fn test_godbolt() {
+  use hyper::server::{self, Server};
+  use http;
+
+  // A stand-in of Compiler Explorer.
+  let handler = |req: server::Request, res: server::Response| {
+    let (req, _) = http::Request::read(req);
+    let json = match req.path.as_str() {
+      "/api/languages" => r#"[{"id": "c++", "name": "C++"}]"#.to_owned(),
+      "/api/compilers" => r#"[{"id": "g132", "name": "x86-64 gcc 13.2", "lang": "c++", "semver": "13.2"}]"#.to_owned(),
+      "/api/compiler/g132/compile" => {
+        let request: serde_json::Value = serde_json::from_str(&req.body).unwrap();
+        assert_eq!(request["options"]["userArguments"], "-O2 '-DX=a b'");
+        assert_eq!(request["files"][0]["filename"], "a.h");
+        if request["options"]["filters"]["execute"].as_bool() == Some(true) {
+          assert_eq!(request["options"]["executeParameters"]["stdin"], "input");
+          r#"{"code": 0, "didExecute": true, "stdout": [{"text": "hello"}], "stderr": [],
+              "buildResult": {"code": 0, "stdout": [], "stderr": [{"text": "warning"}]}}"#
+            .to_owned()
+        } else {
+          r#"{"code": 0, "stdout": [], "stderr": [], "asm": [{"text": "main:"}, {"text": "  ret"}]}"#.to_owned()
+        }
+      }
+      _ => return http::send(res, StatusCode::NotFound, "not found"),
+    };
+    http::send(res, StatusCode::Ok, &json);
+  };
+  let mut listening = Server::http("127.0.0.1:0").unwrap().handle(handler).unwrap();
+  let godbolt = Godbolt::new(Some(format!("http://{}", listening.socket)));
+
+  let compilers = godbolt.get_compiler_info().unwrap();
+  assert_eq!((compilers[0].name.as_str(), compilers[0].language.as_str()), ("g132", "C++"));
+
+  let mut parameter = Parameter::new("int main() {}", "g132");
+  parameter.compiler_option_raw = Some("-O2\n-DX=a b".to_owned());
+  parameter.stdin = Some("input".to_owned());
+  parameter.codes = Some(vec![::wandbox::Code {
+                                file: "a.h".to_owned(),
+                                code: String::new(),
+                              }]);
+  let response = godbolt.compile(parameter.clone()).unwrap();
+  assert_eq!(response.status, 0);
+  assert_eq!(response.compiler_error, Some("warning\n".to_owned()));
+  assert_eq!(response.program_output, Some("hello\n".to_owned()));
+
+  parameter.options("asm");
+  let response = godbolt.compile(parameter.clone()).unwrap();
+  assert_eq!(response.program_output, Some("main:\n  ret\n".to_owned()));
+
+  parameter.compiler = "unknown".to_owned();
+  assert!(godbolt.compile(parameter).is_err());
+  let _ = listening.close();
+}
@@ -0,0 +1,267 @@
+//! Recording of HTTP sessions in the HTTP Archive (HAR) 1.2 format.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use hyper::header::Headers;
+use serde_json;
+
+use util;
+use ResultExt;
+
+/// Headers whose values are replaced by `REDACTED`.
+const SECRET_HEADERS: &'static [&'static str] = &["authorization", "proxy-authorization", "cookie", "set-cookie"];
+const REDACTED: &'static str = "[redacted]";
+
+#[derive(Debug, Serialize)]
+pub struct Har {
+  pub log: Log,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Log {
+  pub version: &'static str,
+  pub creator: Creator,
+  pub entries: Vec<Entry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Creator {
+  pub name: &'static str,
+  pub version: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+  pub started_date_time: String,
+  /// Total time in milliseconds.
+  pub time: f64,
+  pub request: Request,
+  pub response: Response,
+  pub cache: Cache,
+  pub timings: Timings,
+  /// Error which prevented the response, if any.
+  #[serde(rename = "_error")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Request {
+  pub method: String,
+  pub url: String,
+  pub http_version: String,
+  pub cookies: Vec<Header>,
+  pub headers: Vec<Header>,
+  pub query_string: Vec<Header>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub post_data: Option<PostData>,
+  pub headers_size: i64,
+  pub body_size: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Response {
+  pub status: u16,
+  pub status_text: String,
+  pub http_version: String,
+  pub cookies: Vec<Header>,
+  pub headers: Vec<Header>,
+  pub content: Content,
+  #[serde(rename = "redirectURL")]
+  pub redirect_url: String,
+  pub headers_size: i64,
+  pub body_size: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Header {
+  pub name: String,
+  pub value: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostData {
+  pub mime_type: String,
+  pub text: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Content {
+  pub size: i64,
+  pub mime_type: String,
+  pub text: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Cache {}
+
+/// Times of the phases in milliseconds.
+#[derive(Debug, Serialize)]
+pub struct Timings {
+  pub send: f64,
+  pub wait: f64,
+  pub receive: f64,
+}
+
+impl Entry {
+  /// Creates an entry of a request, whose response is filled by `set_response` or `set_error`.
+  pub fn new(started: SystemTime, method: &str, url: &str, headers: &Headers, body: Option<&str>) -> Entry {
+    let headers = convert_headers(headers);
+    let post_data = body.map(|body| {
+      PostData {
+        mime_type: find_header(&headers, "content-type").unwrap_or("").to_owned(),
+        text: body.to_owned(),
+      }
+    });
+    Entry {
+      started_date_time: util::format_time(started),
+      time: 0.0,
+      request: Request {
+        method: method.to_owned(),
+        url: url.to_owned(),
+        http_version: "HTTP/1.1".to_owned(),
+        cookies: Vec::new(),
+        headers: headers,
+        query_string: Vec::new(),
+        post_data: post_data,
+        headers_size: -1,
+        body_size: body.map_or(0, |body| body.len() as i64),
+      },
+      response: Response {
+        status: 0,
+        status_text: String::new(),
+        http_version: String::new(),
+        cookies: Vec::new(),
+        headers: Vec::new(),
+        content: Content {
+          size: 0,
+          mime_type: String::new(),
+          text: String::new(),
+        },
+        redirect_url: String::new(),
+        headers_size: -1,
+        body_size: -1,
+      },
+      cache: Cache {},
+      timings: Timings {
+        send: 0.0,
+        wait: 0.0,
+        receive: 0.0,
+      },
+      error: None,
+    }
+  }
+
+  /// Sets the response, with the times until the headers and the body are received.
+  pub fn set_response(&mut self,
+                      status: u16,
+                      status_text: &str,
+                      version: &str,
+                      headers: &Headers,
+                      body: &str,
+                      wait: Duration,
+                      receive: Duration) {
+    let headers = convert_headers(headers);
+    self.response.status = status;
+    self.response.status_text = status_text.to_owned();
+    self.response.http_version = version.to_owned();
+    self.response.content = Content {
+      size: body.len() as i64,
+      mime_type: find_header(&headers, "content-type").unwrap_or("").to_owned(),
+      text: body.to_owned(),
+    };
+    self.response.body_size = body.len() as i64;
+    self.response.headers = headers;
+    self.timings.wait = millis(wait);
+    self.timings.receive = millis(receive);
+    self.time = millis(wait) + millis(receive);
+  }
+
+  pub fn set_error(&mut self, error: &str, elapsed: Duration) {
+    self.error = Some(error.to_owned());
+    self.timings.wait = millis(elapsed);
+    self.time = millis(elapsed);
+  }
+}
+
+/// Appends entries to a HAR file, which is rewritten after each request so that it is kept
+/// even if the process fails later.
+#[derive(Debug)]
+pub struct Recorder {
+  path: PathBuf,
+  har: Mutex<Har>,
+}
+
+impl Recorder {
+  pub fn new<P: Into<PathBuf>>(path: P) -> Recorder {
+    Recorder {
+      path: path.into(),
+      har: Mutex::new(Har {
+                        log: Log {
+                          version: "1.2",
+                          creator: Creator {
+                            name: env!("CARGO_PKG_NAME"),
+                            version: env!("CARGO_PKG_VERSION"),
+                          },
+                          entries: Vec::new(),
+                        },
+                      }),
+    }
+  }
+
+  pub fn record(&self, entry: Entry) -> ::Result<()> {
+    let mut har = self.har.lock().unwrap();
+    har.log.entries.push(entry);
+    let json = serde_json::to_string_pretty(&*har)? + "\n";
+    File::create(&self.path)
+      .and_then(|mut f| f.write_all(json.as_bytes()))
+      .chain_err(|| format!("failed to write {}", self.path.display()))?;
+    Ok(())
+  }
+}
+
+fn convert_headers(headers: &Headers) -> Vec<Header> {
+  headers.iter()
+         .map(|h| {
+    let name = h.name().to_owned();
+    let value = if SECRET_HEADERS.contains(&name.to_lowercase().as_str()) {
+      REDACTED.to_owned()
+    } else {
+      h.value_string()
+    };
+    Header {
+      name: name,
+      value: value,
+    }
+  })
+         .collect()
+}
+
+fn find_header<'a>(headers: &'a [Header], name: &str) -> Option<&'a str> {
+  headers.iter().find(|h| h.name.to_lowercase() == name).map(|h| h.value.as_str())
+}
+
+fn millis(d: Duration) -> f64 {
+  d.as_secs() as f64 * 1000.0 + d.subsec_nanos() as f64 / 1000000.0
+}
+
+
+#[test]
+fn test_redact_headers() {
+  use hyper::header::{Authorization, Bearer, ContentType};
+  let mut headers = Headers::new();
+  headers.set(Authorization(Bearer { token: "secret".to_owned() }));
+  headers.set(ContentType::json());
+  let entry = Entry::new(::std::time::UNIX_EPOCH, "POST", "http://localhost/api/compile.json", &headers, Some("{}"));
+  assert_eq!(find_header(&entry.request.headers, "authorization"), Some(REDACTED));
+  assert_eq!(entry.request.post_data.unwrap().mime_type, "application/json");
+}
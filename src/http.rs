@@ -0,0 +1,340 @@
+//! HTTP of the backends and the servers: a client of the web APIs, and handling of the requests
+//! to `wan mock-server`, `wan serve` and `wan proxy`.
+
+use std::borrow::Borrow;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+use hyper;
+use hyper::header::{Accept, Authorization, Basic, Bearer, CacheControl, CacheDirective, ContentType, Headers, Host};
+use hyper::method::Method;
+use hyper::server;
+use hyper::status::StatusCode;
+use hyper::uri::RequestUri;
+use hyper_native_tls::{self, NativeTlsClient};
+use hyper_native_tls::native_tls::{Certificate, Pkcs12, TlsConnector};
+use shellexpand;
+
+use cassette::{Cassette, Mode};
+use config::{AuthConfig, Config, TlsConfig};
+use har;
+use ResultExt;
+
+/// Maximum size of a request body.
+pub const MAX_REQUEST_SIZE: u64 = 1024 * 1024;
+
+/// A request whose body is read.
+#[derive(Debug)]
+pub struct Request {
+  pub client: IpAddr,
+  pub method: Method,
+  /// Path without the query.
+  pub path: String,
+  /// Value of the `Host` header, used in the URLs of permlinks.
+  pub host: Option<String>,
+  /// Whether the client asks not to use caches, by `Cache-Control: no-cache` or `no-store`.
+  pub no_cache: bool,
+  pub body: String,
+}
+
+impl Request {
+  /// Reads a request, or returns the status and the message to answer when it cannot be read.
+  pub fn read(req: server::Request) -> (Request, Result<(), (StatusCode, String)>) {
+    let path = match req.uri {
+      RequestUri::AbsolutePath(ref path) => path.split('?').next().unwrap_or("").to_owned(),
+      _ => String::new(),
+    };
+    let host = req.headers.get::<Host>().map(|host| match host.port {
+      Some(port) => format!("{}:{}", host.hostname, port),
+      None => host.hostname.clone(),
+    });
+    let no_cache = req.headers.get::<CacheControl>().map_or(false, |directives| {
+      directives.iter().any(|d| *d == CacheDirective::NoCache || *d == CacheDirective::NoStore)
+    });
+    let mut request = Request {
+      client: req.remote_addr.ip(),
+      method: req.method.clone(),
+      path: path,
+      host: host,
+      no_cache: no_cache,
+      body: String::new(),
+    };
+    let result = match req.take(MAX_REQUEST_SIZE + 1).read_to_string(&mut request.body) {
+      Ok(size) if size as u64 > MAX_REQUEST_SIZE => {
+        Err((StatusCode::PayloadTooLarge, "request is too large".to_owned()))
+      }
+      Ok(_) => Ok(()),
+      // A body which is not UTF-8 is the client's fault, and the other failures of reading are the server's.
+      Err(ref err) if err.kind() == io::ErrorKind::InvalidData => Err((StatusCode::BadRequest, err.to_string())),
+      Err(err) => Err((StatusCode::InternalServerError, err.to_string())),
+    };
+    (request, result)
+  }
+
+  pub fn host(&self) -> Option<&str> {
+    self.host.as_ref().map(String::as_str)
+  }
+}
+
+/// Sends a response, which is JSON when it succeeds.
+pub fn send(mut res: server::Response, status: StatusCode, content: &str) {
+  *res.status_mut() = status;
+  if status == StatusCode::Ok {
+    res.headers_mut().set(ContentType::json());
+  }
+  let result = res.start().and_then(|mut res| {
+    res.write_all(content.as_bytes())?;
+    res.end()
+  });
+  if let Err(err) = result {
+    warn!("failed to send the response: {}", err);
+  }
+}
+
+
+/// A client of a web API, with the settings of TLS, authentication, tracing and cassettes.
+pub struct Client {
+  endpoints: Vec<String>,
+  /// Index of the endpoint in use, which moves forward when it fails.
+  current: AtomicUsize,
+  tls: TlsConfig,
+  auth: Option<AuthConfig>,
+  trace: Option<har::Recorder>,
+  cassette: Option<Cassette>,
+}
+
+impl Client {
+  /// Creates a client which fails over to the next endpoint when one is not available.
+  pub fn new(endpoints: Vec<String>) -> Client {
+    assert!(!endpoints.is_empty(), "no endpoint is given");
+    Client {
+      endpoints: endpoints,
+      current: AtomicUsize::new(0),
+      tls: TlsConfig::default(),
+      auth: None,
+      trace: None,
+      cassette: None,
+    }
+  }
+
+  /// Creates a client of the configured endpoints, or of `default_url` when there is none.
+  pub fn from_config(config: &Config, default_url: &str) -> Client {
+    Self::with_endpoints_of(config, config.endpoints(), default_url)
+  }
+
+  /// Creates a client of a backend other than Wandbox, which ignores the endpoints of Wandbox (see
+  /// `Config::endpoints_for`).
+  pub fn for_backend(config: &Config, backend: &str, default_url: &str) -> Client {
+    Self::with_endpoints_of(config, config.endpoints_for(backend), default_url)
+  }
+
+  fn with_endpoints_of(config: &Config, mut endpoints: Vec<String>, default_url: &str) -> Client {
+    if endpoints.is_empty() {
+      endpoints.push(default_url.to_owned());
+    }
+    let mut client = Client::new(endpoints);
+    client.tls = config.tls.clone();
+    client.auth = config.auth.clone();
+    if let Some(ref path) = config.trace_http {
+      client.trace_http(path.clone());
+    }
+    client.cassette = config.cassette.clone();
+    client
+  }
+
+  /// Records all requests and responses to a HAR file.
+  pub fn trace_http<P: Into<PathBuf>>(&mut self, path: P) {
+    self.trace = Some(har::Recorder::new(path));
+  }
+
+  /// Records the responses to a cassette, or replays them from it without sending requests.
+  pub fn cassette(&mut self, cassette: Cassette) {
+    self.cassette = Some(cassette);
+  }
+
+  pub fn endpoints(&self) -> &[String] {
+    &self.endpoints
+  }
+
+  /// Returns the URL of the endpoint in use.
+  pub fn url(&self) -> &str {
+    &self.endpoints[self.current.load(Ordering::SeqCst)]
+  }
+
+  /// Sends a request and returns the status and the body, e.g. to relay them.
+  ///
+  /// GET requests fail over to the next endpoint.
+  pub fn forward(&self, method: Method, path: &str, body: Option<&str>) -> ::Result<(StatusCode, String)> {
+    if method == Method::Get {
+      return self.fetch(path);
+    }
+    let client = self.client()?;
+    self.request(&client, method, self.url(), path, body)?
+      .chain_err(|| ::ErrorKind::Unreachable(self.url().to_owned()))
+  }
+
+  /// Sends a GET request to the endpoint, without failing over to the others.
+  pub fn get_from(&self, endpoint: &str, path: &str) -> ::Result<(StatusCode, String)> {
+    let client = self.client()?;
+    self.request(&client, Method::Get, endpoint, path, None)?
+      .chain_err(|| ::ErrorKind::Unreachable(endpoint.to_owned()))
+  }
+
+  /// Sends a GET request, trying the endpoints in order.
+  ///
+  /// An endpoint fails when the request cannot be sent or it responds with a server error,
+  /// and is skipped by the later requests.
+  fn fetch(&self, path: &str) -> ::Result<(StatusCode, String)> {
+    let client = self.client()?;
+    let last = self.endpoints.len() - 1;
+    for i in self.current.load(Ordering::SeqCst)..self.endpoints.len() {
+      let reason = match self.request(&client, Method::Get, &self.endpoints[i], path, None)? {
+        Ok((status, body)) => {
+          if !status.is_server_error() {
+            self.current.store(i, Ordering::SeqCst);
+            return Ok((status, body));
+          }
+          if i == last {
+            bail!(::ErrorKind::HttpStatus(format!("{}{}", self.endpoints[i], path), status.to_string()));
+          }
+          status.to_string()
+        }
+        Err(err) => {
+          if i == last {
+            return Err(err).chain_err(|| ::ErrorKind::Unreachable(self.endpoints[i].clone()));
+          }
+          err.to_string()
+        }
+      };
+      warn!("{} is not available ({}); trying {}",
+            self.endpoints[i],
+            reason,
+            self.endpoints[i + 1]);
+    }
+    unreachable!()
+  }
+
+  /// Sends a request and reads the whole response, recording them when tracing.
+  ///
+  /// The outer error is the failure of recording or replaying, and the inner one is of the request.
+  fn request(&self,
+             client: &hyper::Client,
+             method: Method,
+             endpoint: &str,
+             path: &str,
+             body: Option<&str>)
+             -> ::Result<hyper::Result<(StatusCode, String)>> {
+    if let Some(ref cassette) = self.cassette {
+      if cassette.mode() == Mode::Replay {
+        let (status, response) = cassette.load(method.as_ref(), path, body)?;
+        debug!("{} {} is replayed from {}", method, path, cassette.dir().display());
+        return Ok(Ok((StatusCode::from_u16(status), response)));
+      }
+    }
+
+    let url = &format!("{}{}", endpoint, path);
+    let mut headers = Headers::new();
+    headers.set(Accept::json());
+    self.authorize(&mut headers);
+    if body.is_some() {
+      headers.set(ContentType::json());
+    }
+    let mut entry = self.trace
+                        .as_ref()
+                        .map(|_| har::Entry::new(SystemTime::now(), method.as_ref(), url, &headers, body));
+
+    debug!("{} {}", method, url);
+    if let Some(body) = body {
+      trace!("request body: {}", body);
+    }
+    let start = Instant::now();
+    let mut request = client.request(method.clone(), url).headers(headers);
+    if let Some(body) = body {
+      request = request.body(body);
+    }
+    let result = request.send().and_then(|mut res| {
+      let wait = start.elapsed();
+      let mut buf = String::new();
+      res.read_to_string(&mut buf)?;
+      if let Some(ref mut entry) = entry {
+        entry.set_response(res.status.to_u16(),
+                           res.status.canonical_reason().unwrap_or(""),
+                           &res.version.to_string(),
+                           &res.headers,
+                           &buf,
+                           wait,
+                           start.elapsed() - wait);
+      }
+      Ok((res.status, buf))
+    });
+
+    match result {
+      Ok((status, ref body)) => {
+        info!("{} {} -> {} ({} ms)", method, url, status, millis(start.elapsed()));
+        trace!("response body: {}", body);
+      }
+      Err(ref err) => info!("{} {} -> {} ({} ms)", method, url, err, millis(start.elapsed())),
+    }
+
+    if let (Some(cassette), &Ok((status, ref response))) = (self.cassette.as_ref(), &result) {
+      cassette.save(method.as_ref(), path, body, status.to_u16(), response)?;
+    }
+    if let (Some(trace), Some(mut entry)) = (self.trace.as_ref(), entry) {
+      if let Err(ref err) = result {
+        entry.set_error(&err.to_string(), start.elapsed());
+      }
+      trace.record(entry)?;
+    }
+    Ok(result)
+  }
+
+  fn client(&self) -> ::Result<hyper::Client> {
+    let tls = if self.tls.ca_file.is_none() && self.tls.identity.is_none() {
+      hyper_native_tls::NativeTlsClient::new()?
+    } else {
+      let mut builder = TlsConnector::builder()?;
+      if let Some(ref path) = self.tls.ca_file {
+        builder.add_root_certificate(Certificate::from_der(&read_file(path)?)?)?;
+      }
+      if let Some(ref path) = self.tls.identity {
+        let password = self.tls.identity_password.as_ref().map(String::as_str).unwrap_or("");
+        builder.identity(Pkcs12::from_der(&read_file(path)?, password)?)?;
+      }
+      NativeTlsClient::from(builder.build()?)
+    };
+    let connector = hyper::net::HttpsConnector::new(tls);
+    Ok(hyper::Client::with_connector(connector))
+  }
+
+  fn authorize(&self, headers: &mut Headers) {
+    match self.auth {
+      Some(AuthConfig { token: Some(ref token), .. }) => {
+        headers.set(Authorization(Bearer { token: token.clone() }))
+      }
+      Some(AuthConfig { username: Some(ref username), ref password, .. }) => {
+        headers.set(Authorization(Basic {
+                                    username: username.clone(),
+                                    password: password.clone(),
+                                  }))
+      }
+      _ => (),
+    }
+  }
+}
+
+fn millis(d: Duration) -> u64 {
+  d.as_secs() * 1000 + (d.subsec_nanos() / 1000000) as u64
+}
+
+fn read_file(path: &str) -> ::Result<Vec<u8>> {
+  let path = shellexpand::full(path)?;
+  let mut buf = Vec::new();
+  File::open(path.borrow() as &str)
+    .and_then(|mut f| f.read_to_end(&mut buf))
+    .chain_err(|| format!("failed to read {}", path))?;
+  Ok(buf)
+}
@@ -1,50 +1,303 @@
 use std::collections::HashMap;
 
+use regex::Regex;
+use serde_json;
+
+use config::LanguageConfig;
+
 lazy_static!{
-  static ref LANGUAGES: HashMap<&'static str, (&'static str, Vec<&'static str>)> = {
-    make_languages()
+  static ref LANGUAGES: Languages = Languages::builtin();
+
+  static ref VIM_MODELINE: Regex =
+    Regex::new(r"(?:^|\s)(?:vi|vim|ex):.*?\b(?:ft|filetype|syntax)=([\w+#-]+)").unwrap();
+  static ref EMACS_MODELINE: Regex = Regex::new(r"-\*-(.+?)-\*-").unwrap();
+
+  /// Patterns of code characteristic of a language, tried in order.
+  static ref HEURISTICS: Vec<(Regex, &'static str)> = {
+    let patterns = [
+      (r"(?m)^<\?php", "PHP"),
+      (r"(?m)^\s*(?:pub\s+)?fn\s+main\s*\(|^\s*use\s+std::|\blet\s+mut\b", "Rust"),
+      (r"(?m)^\s*#include\s*<\w+>|\bstd::(?:\w+\s*<|cout\b)|^\s*template\s*<|^\s*using\s+namespace\b", "C++"),
+      (r#"(?m)^\s*#include\s*[<"]"#, "C"),
+      (r"(?m)^\s*package\s+main\b", "Go"),
+      (r"(?m)^\s*using\s+System\s*;|\bstatic\s+void\s+Main\s*\(", "C#"),
+      (r"\bpublic\s+static\s+void\s+main\s*\(", "Java"),
+      (r"(?m)^\s*import\s+std\.\w+\s*;", "D"),
+      (r"(?m)^\s*module\s+[A-Z][\w.]*\s+where\b|^main\s*=", "Haskell"),
+      (r"(?m)^\s*let\s+\(\)\s*=", "OCaml"),
+      (r"(?m)^\s*use\s+strict\s*;", "Perl"),
+      (r"(?m)^\s*def\s+\w+\s*\(.*\)\s*:\s*$|^\s*from\s+[\w.]+\s+import\b|^\s*import\s+\w+\s*$|^\s*print\(",
+       "Python"),
+      (r#"(?m)^\s*(?:puts|require)\s+['"]"#, "Ruby"),
+      (r#"\bconsole\.log\s*\(|\brequire\s*\(\s*['"]"#, "JavaScript"),
+      (r"(?m)^\s*(?:echo|printf)\s", "Bash script"),
+    ];
+    patterns.iter().map(|&(re, name)| (Regex::new(re).unwrap(), name)).collect()
+  };
+}
+
+/// Mapping from a language to its default compiler and file extensions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Language {
+  pub name: String,
+  pub compiler: String,
+  #[serde(default)]
+  pub extensions: Vec<String>,
+  /// Names of interpreters in a shebang line, without version suffix (e.g. `python`).
+  #[serde(default)]
+  pub interpreters: Vec<String>,
+  /// Names used by Vim/Emacs modelines, in addition to the extensions and the language name.
+  #[serde(default)]
+  pub filetypes: Vec<String>,
+  /// Markers of comments, used to find directives.
+  #[serde(default)]
+  pub comments: Vec<String>,
+}
+
+/// Table of known languages.
+#[derive(Debug, Clone)]
+pub struct Languages {
+  languages: Vec<Language>,
+}
+
+impl Languages {
+  /// Returns the built-in table, loaded from `languages.json`.
+  pub fn builtin() -> Languages {
+    let languages = serde_json::from_str(include_str!("languages.json")).expect("invalid languages.json");
+    Languages { languages: languages }
+  }
+
+  /// Returns the built-in table, with the overrides of the configuration applied.
+  ///
+  /// An extension listed by an override is removed from all other languages, so that the
+  /// configuration always takes precedence.
+  pub fn with_config(config: &HashMap<String, LanguageConfig>) -> ::Result<Languages> {
+    let mut languages = Self::builtin();
+    let mut names: Vec<_> = config.keys().collect();
+    names.sort();
+    for name in names {
+      languages.apply(name, &config[name])?;
+    }
+    Ok(languages)
+  }
+
+  fn apply(&mut self, name: &str, config: &LanguageConfig) -> ::Result<()> {
+    if let Some(ref extensions) = config.extensions {
+      for language in self.languages.iter_mut().filter(|l| l.name != name) {
+        language.extensions.retain(|ext| !extensions.contains(ext));
+      }
+    }
+
+    if let Some(language) = self.languages.iter_mut().find(|l| l.name == name) {
+      if let Some(ref compiler) = config.compiler {
+        language.compiler = compiler.clone();
+      }
+      if let Some(ref extensions) = config.extensions {
+        language.extensions = extensions.clone();
+      }
+      return Ok(());
+    }
+
+    let compiler = match config.compiler {
+      Some(ref compiler) => compiler.clone(),
+      None => bail!("language `{}` is not built-in and needs a default compiler", name),
+    };
+    self.languages.push(Language {
+      name: name.to_owned(),
+      compiler: compiler,
+      extensions: config.extensions.clone().unwrap_or_default(),
+      interpreters: Vec::new(),
+      filetypes: Vec::new(),
+      comments: Vec::new(),
+    });
+    Ok(())
+  }
+
+  pub fn iter<'a>(&'a self) -> ::std::slice::Iter<'a, Language> {
+    self.languages.iter()
+  }
+
+  pub fn get(&self, name: &str) -> Option<&Language> {
+    self.languages.iter().find(|l| l.name == name)
+  }
+
+  pub fn find_by_extension(&self, ext: &str) -> Option<&Language> {
+    self.languages.iter().find(|l| l.extensions.iter().any(|e| e == ext))
+  }
+
+  /// Finds a language by the name of an interpreter, e.g. `python3` or `bash`.
+  pub fn find_by_interpreter(&self, interpreter: &str) -> Option<&Language> {
+    let suffix = interpreter.chars().rev().take_while(|&c| c.is_digit(10) || c == '.' || c == '-').count();
+    let name = &interpreter[..interpreter.len() - suffix];
+    self.languages.iter().find(|l| l.interpreters.iter().any(|i| i == interpreter || i == name))
+  }
+
+  /// Finds a language by the file type of a Vim/Emacs modeline, e.g. `cpp` or `c++`.
+  pub fn find_by_filetype(&self, filetype: &str) -> Option<&Language> {
+    let filetype = filetype.to_lowercase();
+    self.languages
+        .iter()
+        .find(|l| l.filetypes.contains(&filetype))
+        .or_else(|| self.find_by_extension(&filetype))
+        .or_else(|| self.languages.iter().find(|l| l.name.to_lowercase() == filetype))
+  }
+
+  /// Guesses the language from the content of a source.
+  ///
+  /// The shebang line is examined first, then Vim/Emacs modelines and finally some
+  /// characteristic patterns of the code.
+  pub fn detect(&self, code: &str) -> Option<&Language> {
+    if let Some(interpreter) = parse_shebang(code) {
+      if let Some(language) = self.find_by_interpreter(interpreter) {
+        debug!("detected {} from the shebang (`{}`)", language.name, interpreter);
+        return Some(language);
+      }
+    }
+    if let Some(filetype) = parse_modeline(code) {
+      if let Some(language) = self.find_by_filetype(filetype) {
+        debug!("detected {} from the modeline (`{}`)", language.name, filetype);
+        return Some(language);
+      }
+    }
+    let language = HEURISTICS.iter()
+                             .find(|&&(ref re, _)| re.is_match(code))
+                             .and_then(|&(_, name)| self.get(name));
+    match language {
+      Some(language) => debug!("detected {} from the content", language.name),
+      None => debug!("failed to detect the language from the content"),
+    }
+    language
+  }
+}
+
+/// Extracts the name of the interpreter from a shebang line.
+fn parse_shebang(code: &str) -> Option<&str> {
+  let line = match code.lines().next() {
+    Some(line) if line.starts_with("#!") && !line.starts_with("#![") => line,
+    _ => return None,
   };
+  let mut words = line[2..].split_whitespace();
+  let program = match words.next() {
+    Some(program) => program.rsplit('/').next().unwrap(),
+    None => return None,
+  };
+  if program == "env" {
+    words.find(|w| !w.starts_with('-') && !w.contains('='))
+  } else {
+    Some(program)
+  }
 }
 
-fn make_languages() -> HashMap<&'static str, (&'static str, Vec<&'static str>)> {
-  let mut mappings = HashMap::new();
-  mappings.insert("Bash script", ("bash", vec!["sh", "bash"]));
-  mappings.insert("C", ("gcc-head-c", vec!["c", "h"]));
-  mappings.insert("C#", ("mono-head", vec!["cs"]));
-  mappings.insert("C++", ("gcc-head", vec!["cpp,cxx,cc,hpp,hxx,hh"]));
-  mappings.insert("CoffeeScript", ("coffeescript-head", vec!["coffee"]));
-  mappings.insert("CPP", ("gcc-head-pp", vec![]));
-  mappings.insert("Crystal", ("crystal-head", vec!["cr"]));
-  mappings.insert("D", ("ldc-head", vec!["d"]));
-  mappings.insert("Elixir", ("elixir-head", vec!["ex", "exs"]));
-  mappings.insert("Erlang", ("erlang-head", vec!["erl"]));
-  mappings.insert("Go", ("go-head", vec!["go"]));
-  mappings.insert("Groovy", ("groovy-head", vec!["groovy"]));
-  mappings.insert("Haskell", ("ghc-head", vec!["hs"]));
-  mappings.insert("Java", ("openjdk-head", vec!["java"]));
-  mappings.insert("JavaScript", ("nodejs-head", vec!["js"]));
-  mappings.insert("Lazy K", ("lazyk", vec!["lazy"]));
-  mappings.insert("Lisp", ("clisp-2.49", vec!["lisp"]));
-  mappings.insert("Lua", ("lua-5.3.4", vec!["lua"]));
-  mappings.insert("Nim", ("nim-head", vec!["nim"]));
-  mappings.insert("OCaml", ("ocaml-head", vec!["ml"]));
-  mappings.insert("Pascal", ("fpc-head", vec!["pas"]));
-  mappings.insert("Perl", ("perl-head", vec!["pl"]));
-  mappings.insert("PHP", ("php-head", vec!["php"]));
-  mappings.insert("Pony", ("pony-head", vec!["pony"]));
-  mappings.insert("Python", ("cpython-head", vec!["py"]));
-  mappings.insert("Rill", ("rill-head", vec!["rill"]));
-  mappings.insert("Ruby", ("ruby-head", vec!["rb"]));
-  mappings.insert("Rust", ("rust-head", vec!["rs"]));
-  mappings.insert("Scala", ("scala-head", vec!["scala"]));
-  mappings.insert("SQL", ("sqlite-head", vec!["sql"]));
-  mappings.insert("Swift", ("swift-head", vec!["swift"]));
-  mappings.insert("Vim script", ("vim-head", vec!["vim"]));
-  mappings
+/// Extracts the file type from a Vim/Emacs modeline in the first or last lines.
+fn parse_modeline(code: &str) -> Option<&str> {
+  let lines: Vec<_> = code.lines().collect();
+  let head = lines.iter().take(5);
+  let tail = lines.iter().skip(5).rev().take(5);
+  for line in head.chain(tail) {
+    if let Some(cap) = VIM_MODELINE.captures(line) {
+      return cap.get(1).map(|m| m.as_str());
+    }
+    if let Some(cap) = EMACS_MODELINE.captures(line) {
+      let vars = cap.get(1).unwrap().as_str();
+      if !vars.contains(':') {
+        return Some(vars.trim());
+      }
+      for var in vars.split(';') {
+        let mut kv = var.splitn(2, ':');
+        if let (Some(key), Some(value)) = (kv.next(), kv.next()) {
+          if key.trim().to_lowercase() == "mode" {
+            return Some(value.trim());
+          }
+        }
+      }
+    }
+  }
+  None
 }
 
 pub fn get_compiler_from_ext(ext: &str) -> Option<&'static str> {
-  LANGUAGES.iter()
-           .find(|&(_, val)| val.1.contains(&ext))
-           .map(|(_, val)| val.0)
+  LANGUAGES.find_by_extension(ext).map(|l| l.compiler.as_str())
+}
+
+
+#[test]
+fn test_builtin_languages() {
+  let languages = Languages::builtin();
+  for (i, language) in languages.iter().enumerate() {
+    assert!(languages.iter().skip(i + 1).all(|l| l.name != language.name),
+            "duplicated language: {}",
+            language.name);
+    for ext in &language.extensions {
+      assert!(!ext.contains(',') && !ext.starts_with('.'), "invalid extension: {}", ext);
+      assert_eq!(languages.find_by_extension(ext).map(|l| &l.name), Some(&language.name));
+    }
+  }
+}
+
+#[test]
+fn test_languages_in_compiler_info() {
+  use wandbox::CompilerInfo;
+  let src = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/list.json"));
+  let compilers: Vec<CompilerInfo> = serde_json::from_str(src).unwrap();
+  let languages = Languages::builtin();
+  for compiler in &compilers {
+    assert!(languages.get(&compiler.language).is_some(),
+            "missing language: {}",
+            compiler.language);
+  }
+}
+
+#[test]
+fn test_get_compiler_from_ext() {
+  assert_eq!(get_compiler_from_ext("cpp"), Some("gcc-head"));
+  assert_eq!(get_compiler_from_ext("hh"), Some("gcc-head"));
+  assert_eq!(get_compiler_from_ext("rs"), Some("rust-head"));
+  assert_eq!(get_compiler_from_ext("cpp,cxx"), None);
+}
+
+#[test]
+fn test_detect_language() {
+  let languages = Languages::builtin();
+  let detect = |code: &str| languages.detect(code).map(|l| l.name.clone());
+  assert_eq!(detect("#!/usr/bin/env python3\nprint(1)\n"), Some("Python".to_owned()));
+  assert_eq!(detect("#!/bin/bash\nls\n"), Some("Bash script".to_owned()));
+  assert_eq!(detect("#!/usr/bin/env -S ruby -w\n"), Some("Ruby".to_owned()));
+  assert_eq!(detect("# vim: set ft=cpp :\nint main() {}\n"), Some("C++".to_owned()));
+  assert_eq!(detect("-- -*- mode: lua; coding: utf-8 -*-\n"), Some("Lua".to_owned()));
+  assert_eq!(detect(";; -*- lisp -*-\n"), Some("Lisp".to_owned()));
+  assert_eq!(detect("#include <iostream>\nint main() {}\n"), Some("C++".to_owned()));
+  assert_eq!(detect("#include <stdio.h>\nint main() {}\n"), Some("C".to_owned()));
+  assert_eq!(detect("#![allow(dead_code)]\nfn main() {}\n"), Some("Rust".to_owned()));
+  assert_eq!(detect("package main\nimport \"fmt\"\n"), Some("Go".to_owned()));
+  assert_eq!(detect("use std::io;\n\nstruct Point;\n"), Some("Rust".to_owned()));
+  assert_eq!(detect("template <typename T>\nT twice(T x) { return std::move(x); }\n"), Some("C++".to_owned()));
+  assert_eq!(detect("class Point:\n    def __init__(self):\n        pass\n"), Some("Python".to_owned()));
+  assert_eq!(detect("hello, world\n"), None);
+}
+
+#[test]
+fn test_languages_with_config() {
+  let mut config = HashMap::new();
+  config.insert("C++".to_owned(),
+                LanguageConfig {
+                  compiler: Some("clang-head".to_owned()),
+                  extensions: Some(vec!["cpp".to_owned(), "h".to_owned()]),
+                });
+  config.insert("Brainfuck".to_owned(),
+                LanguageConfig {
+                  compiler: Some("bf".to_owned()),
+                  extensions: Some(vec!["bf".to_owned()]),
+                });
+  let languages = Languages::with_config(&config).unwrap();
+  assert_eq!(languages.find_by_extension("cpp").map(|l| l.compiler.as_str()), Some("clang-head"));
+  assert_eq!(languages.find_by_extension("h").map(|l| l.name.as_str()), Some("C++"));
+  assert!(languages.find_by_extension("cc").is_none());
+  assert_eq!(languages.find_by_extension("bf").map(|l| l.compiler.as_str()), Some("bf"));
+
+  config.insert("Whitespace".to_owned(),
+                LanguageConfig {
+                  compiler: None,
+                  extensions: Some(vec!["ws".to_owned()]),
+                });
+  assert!(Languages::with_config(&config).is_err());
 }
@@ -1,11 +1,17 @@
+extern crate env_logger;
 extern crate hyper;
 extern crate hyper_native_tls;
+#[cfg(unix)]
+extern crate libc;
 extern crate regex;
 extern crate serde;
 extern crate serde_json;
 extern crate shellexpand;
 extern crate shlex;
+extern crate strsim;
 extern crate clap;
+extern crate glob;
+extern crate toml;
 extern crate url;
 #[macro_use]
 extern crate serde_derive;
@@ -13,10 +19,28 @@ extern crate serde_derive;
 extern crate error_chain;
 #[macro_use]
 extern crate lazy_static;
+#[macro_use]
+extern crate log;
 
 pub mod app;
+pub mod backend;
+pub mod cassette;
 pub mod config;
+pub mod directive;
+pub mod godbolt;
+pub mod har;
+pub mod http;
 pub mod language;
+pub mod local;
+pub mod logger;
+pub mod manifest;
+pub mod mock;
+pub mod options;
+pub mod playground;
+pub mod preset;
+pub mod proxy;
+pub mod report;
+pub mod server;
 pub mod util;
 pub mod wandbox;
 
@@ -30,4 +54,37 @@ error_chain! {
     UrlParse(::url::ParseError);
     ShellExpand(::shellexpand::LookupError<::std::env::VarError>);
   }
+
+  errors {
+    UnknownCompiler(name: String) {
+      description("unknown compiler")
+      display("unknown compiler `{}`", name)
+    }
+    UnknownPreset(name: String, similar: Option<String>) {
+      description("unknown preset")
+      display("unknown preset `{}`{}",
+              name,
+              similar.as_ref().map_or(String::new(), |s| format!("; did you mean `{}`?", s)))
+    }
+    InvalidConfig(path: String, problems: String) {
+      description("invalid configuration")
+      display("invalid configuration {}:\n  {}", path, problems)
+    }
+    Unreachable(url: String) {
+      description("endpoint is not reachable")
+      display("failed to connect to {}", url)
+    }
+    NoRecording(request: String, dir: String) {
+      description("request is not recorded")
+      display("{} is not recorded in the cassette {}", request, dir)
+    }
+    HttpStatus(url: String, status: String) {
+      description("server responded with an error")
+      display("{} responded with {}", url, status)
+    }
+    InvalidParameter(message: String) {
+      description("invalid parameter")
+      display("{}", message)
+    }
+  }
 }
@@ -0,0 +1,467 @@
+//! A backend which compiles and runs the code with the toolchains installed locally.
+//!
+//! Each compiler is described in the `local` section of the configuration, with the commands to
+//! compile and run the code and the switches in the format of Wandbox's `list.json`.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json;
+use shlex;
+
+use backend::Backend;
+use config::Config;
+use options::OptionSet;
+use util::{self, Either, TempDir};
+use wandbox::{CompilerInfo, CompilerSwitch, CompilerSwitchMultiOptions, Parameter, Response};
+use ResultExt;
+
+const DEFAULT_TIMEOUT: u64 = 10;
+const DEFAULT_OUTPUT_LIMIT: usize = 1024 * 1024;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct LocalConfig {
+  /// Time limit of each command, in seconds.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub timeout: Option<u64>,
+  /// Maximum size of each output of the commands, in bytes.
+  #[serde(rename = "output-limit")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub output_limit: Option<usize>,
+  /// CPU time limit of each command, in seconds. Defaults to `timeout`.
+  #[serde(rename = "cpu-time")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub cpu_time: Option<u64>,
+  /// Maximum size of the virtual memory of each process, in bytes.
+  #[serde(rename = "memory-limit")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub memory_limit: Option<u64>,
+  /// Maximum number of processes of the user, which the commands cannot exceed by spawning more.
+  #[serde(rename = "process-limit")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub process_limit: Option<u64>,
+  #[serde(default)]
+  pub compilers: HashMap<String, LocalCompiler>,
+}
+
+/// A local toolchain, registered under the name of a Wandbox compiler.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalCompiler {
+  pub language: String,
+  #[serde(rename = "display-name")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub display_name: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub version: Option<String>,
+  /// File name of the main source, e.g. `prog.cc`.
+  pub source: String,
+  /// Command to compile the source, to which the flags of the options and the raw compiler
+  /// arguments are appended. Interpreters have none.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub compile: Option<String>,
+  /// Command to run the program, to which the raw runtime arguments are appended.
+  pub run: String,
+  #[serde(default)]
+  pub switches: Vec<Either<CompilerSwitch, CompilerSwitchMultiOptions>>,
+}
+
+impl LocalCompiler {
+  fn to_info(&self, name: &str) -> CompilerInfo {
+    CompilerInfo {
+      name: name.to_owned(),
+      version: self.version.clone().unwrap_or_else(|| "local".to_owned()),
+      language: self.language.clone(),
+      display_name: self.display_name.clone().unwrap_or_else(|| name.to_owned()),
+      compiler_option_raw: self.compile.is_some(),
+      runtime_option_raw: true,
+      display_compile_command: self.compile.clone().unwrap_or_default(),
+      switches: self.switches.clone(),
+    }
+  }
+}
+
+pub struct Local {
+  timeout: Duration,
+  output_limit: usize,
+  limits: Limits,
+  compilers: HashMap<String, LocalCompiler>,
+}
+
+/// Limits of the resources of each command, set by `setrlimit` on Unix.
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+  cpu_time: u64,
+  memory: Option<u64>,
+  processes: Option<u64>,
+}
+
+/// Output of a command.
+struct Output {
+  status: ExitStatus,
+  stdout: String,
+  stderr: String,
+  timed_out: bool,
+}
+
+impl Local {
+  pub fn new(config: LocalConfig) -> Local {
+    let timeout = config.timeout.unwrap_or(DEFAULT_TIMEOUT);
+    Local {
+      timeout: Duration::from_secs(timeout),
+      output_limit: config.output_limit.unwrap_or(DEFAULT_OUTPUT_LIMIT),
+      limits: Limits {
+        cpu_time: config.cpu_time.unwrap_or(timeout),
+        memory: config.memory_limit,
+        processes: config.process_limit,
+      },
+      compilers: config.compilers,
+    }
+  }
+
+  pub fn from_config(config: &Config) -> Local {
+    Self::new(config.local.clone())
+  }
+
+  /// Returns the compilers, sorted by name.
+  pub fn compilers(&self) -> Vec<CompilerInfo> {
+    let mut compilers: Vec<_> = self.compilers.iter().map(|(name, compiler)| compiler.to_info(name)).collect();
+    compilers.sort_by(|a, b| a.name.cmp(&b.name));
+    compilers
+  }
+
+  fn compile_in(&self, dir: &Path, param: &Parameter) -> ::Result<Response> {
+    let compiler = match self.compilers.get(&param.compiler) {
+      Some(compiler) => compiler,
+      None => bail!(::ErrorKind::UnknownCompiler(param.compiler.clone())),
+    };
+
+    write_source(dir, &compiler.source, &param.code)?;
+    for code in param.codes.iter().flat_map(|codes| codes.iter()) {
+      write_source(dir, &code.file, &code.code)?;
+    }
+
+    let mut response = Response::default();
+
+    if let Some(ref command) = compiler.compile {
+      let info = compiler.to_info(&param.compiler);
+      let mut options = OptionSet::new(&info);
+      if let Some(ref names) = param.options {
+        options.apply(names).chain_err(|| ::ErrorKind::InvalidParameter("invalid options".to_owned()))?;
+      }
+      let mut args = split_command(command)?;
+      args.extend(options.display_flags().iter().flat_map(|flags| flags.split_whitespace()).map(ToOwned::to_owned));
+      args.extend(util::raw_args(&param.compiler_option_raw));
+
+      let output = self.execute(dir, &args, None)?;
+      response.compiler_message = Some(format!("{}{}", output.stdout, output.stderr));
+      response.compiler_output = Some(output.stdout);
+      response.compiler_error = Some(output.stderr);
+      if output.timed_out || !output.status.success() {
+        set_status(&mut response, &output.status, output.timed_out);
+        return Ok(response);
+      }
+    }
+
+    let mut args = split_command(&compiler.run)?;
+    args.extend(util::raw_args(&param.runtime_option_raw));
+    let output = self.execute(dir, &args, param.stdin.as_ref().map(String::as_str))?;
+    set_status(&mut response, &output.status, output.timed_out);
+    let mut message = format!("{}{}", output.stdout, output.stderr);
+    if output.timed_out {
+      message.push_str(&format!("wan: time limit of {} s exceeded\n", self.timeout.as_secs()));
+    }
+    response.program_message = Some(message);
+    response.program_output = Some(output.stdout);
+    response.program_error = Some(output.stderr);
+    Ok(response)
+  }
+
+  /// Runs a command in the directory, killing it when it exceeds the time limit.
+  fn execute(&self, dir: &Path, args: &[String], stdin: Option<&str>) -> ::Result<Output> {
+    let command = util::shell_join(args);
+    info!("local: {}", command);
+
+    // A relative path to the program is resolved in the working directory of the child.
+    let program = if args[0].contains('/') && Path::new(&args[0]).is_relative() {
+      dir.join(&args[0])
+    } else {
+      PathBuf::from(&args[0])
+    };
+    let mut cmd = Command::new(program);
+    cmd.args(&args[1..])
+       .current_dir(dir)
+       .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+       .stdout(Stdio::piped())
+       .stderr(Stdio::piped());
+    isolate(&mut cmd, self.limits);
+    let mut child = cmd.spawn().chain_err(|| format!("failed to execute `{}`", command))?;
+
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+      let input = input.to_owned();
+      thread::spawn(move || pipe.write_all(input.as_bytes()));
+    }
+    let stdout = child.stdout.take().map(|pipe| self.read_in_background(pipe));
+    let stderr = child.stderr.take().map(|pipe| self.read_in_background(pipe));
+
+    let start = Instant::now();
+    let mut timed_out = false;
+    let status = loop {
+      if let Some(status) = child.try_wait()? {
+        break status;
+      }
+      if start.elapsed() > self.timeout {
+        warn!("`{}` is killed after {} s", command, self.timeout.as_secs());
+        timed_out = true;
+        let _ = kill_process_group(&mut child);
+        break child.wait()?;
+      }
+      thread::sleep(Duration::from_millis(10));
+    };
+
+    // The processes which left the group of a killed one may keep the pipes open.
+    let wait = if timed_out {
+      Duration::from_millis(100)
+    } else {
+      self.timeout
+    };
+    let receive = |output: Option<Receiver<String>>| {
+      output.and_then(|rx| rx.recv_timeout(wait).ok()).unwrap_or_default()
+    };
+    Ok(Output {
+      status: status,
+      stdout: receive(stdout),
+      stderr: receive(stderr),
+      timed_out: timed_out,
+    })
+  }
+
+  fn read_in_background<R: Read + Send + 'static>(&self, pipe: R) -> Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    let limit = self.output_limit;
+    thread::spawn(move || tx.send(read_limited(pipe, limit)));
+    rx
+  }
+}
+
+impl Backend for Local {
+  fn get_compiler_info_raw(&self) -> ::Result<Box<Read>> {
+    let json = serde_json::to_string(&self.compilers())?;
+    Ok(Box::new(io::Cursor::new(json.into_bytes())))
+  }
+
+  fn get_compiler_info(&self) -> ::Result<Vec<CompilerInfo>> {
+    Ok(self.compilers())
+  }
+
+  fn compile(&self, param: Parameter) -> ::Result<Response> {
+    let dir = TempDir::new()?;
+    self.compile_in(dir.path(), &param)
+  }
+}
+
+/// Writes a source file, whose name must be relative and stay inside the directory.
+fn write_source(dir: &Path, name: &str, code: &str) -> ::Result<()> {
+  let path = Path::new(name);
+  if name.is_empty() || !path.components().all(|c| match c {
+    Component::Normal(_) => true,
+    _ => false,
+  }) {
+    bail!(::ErrorKind::InvalidParameter(format!("invalid file name `{}`", name)));
+  }
+  let path = dir.join(path);
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  File::create(&path)
+    .and_then(|mut f| f.write_all(code.as_bytes()))
+    .chain_err(|| format!("failed to write {}", path.display()))?;
+  Ok(())
+}
+
+fn split_command(command: &str) -> ::Result<Vec<String>> {
+  match shlex::split(command) {
+    Some(ref words) if !words.is_empty() => Ok(words.clone()),
+    _ => bail!("invalid command: {}", command),
+  }
+}
+
+/// Reads the output up to `limit` bytes, and discards the rest with a notice at the end.
+fn read_limited<R: Read>(reader: R, limit: usize) -> String {
+  let mut buf = Vec::new();
+  let mut reader = reader.take(limit as u64);
+  let _ = reader.read_to_end(&mut buf);
+  let rest = io::copy(&mut reader.into_inner(), &mut io::sink()).unwrap_or(0);
+  let mut output = String::from_utf8_lossy(&buf).into_owned();
+  if rest > 0 {
+    if !output.is_empty() && !output.ends_with('\n') {
+      output.push('\n');
+    }
+    output.push_str(&format!("wan: output truncated at {} bytes\n", limit));
+  }
+  output
+}
+
+fn set_status(response: &mut Response, status: &ExitStatus, timed_out: bool) {
+  response.status = status.code().unwrap_or(1);
+  response.signal = signal_of(status).map(ToOwned::to_owned);
+  if timed_out {
+    response.signal = Some("Killed".to_owned());
+  }
+}
+
+#[cfg(unix)]
+fn signal_of(status: &ExitStatus) -> Option<&'static str> {
+  use std::os::unix::process::ExitStatusExt;
+  status.signal().map(|signal| match signal {
+    6 => "Aborted",
+    8 => "Floating point exception",
+    9 => "Killed",
+    11 => "Segmentation fault",
+    15 => "Terminated",
+    24 => "CPU time limit exceeded",
+    _ => "Signal",
+  })
+}
+
+#[cfg(not(unix))]
+fn signal_of(_: &ExitStatus) -> Option<&'static str> {
+  None
+}
+
+/// Makes the child the leader of a new process group, so that the processes it spawns are killed with it,
+/// and limits its resources.
+#[cfg(unix)]
+fn isolate(command: &mut Command, limits: Limits) {
+  use std::os::unix::process::CommandExt;
+  unsafe {
+    command.before_exec(move || {
+      if libc::setsid() < 0 {
+        return Err(io::Error::last_os_error());
+      }
+      // The soft limit of CPU time sends SIGXCPU, and the hard one a second later SIGKILL.
+      let resources = [(libc::RLIMIT_CPU, Some(limits.cpu_time), 1),
+                       (libc::RLIMIT_AS, limits.memory, 0),
+                       (libc::RLIMIT_NPROC, limits.processes, 0)];
+      for &(resource, limit, grace) in &resources {
+        if let Some(limit) = limit {
+          let rlimit = libc::rlimit {
+            rlim_cur: limit as libc::rlim_t,
+            rlim_max: (limit + grace) as libc::rlim_t,
+          };
+          if libc::setrlimit(resource, &rlimit) < 0 {
+            return Err(io::Error::last_os_error());
+          }
+        }
+      }
+      Ok(())
+    });
+  }
+}
+
+#[cfg(not(unix))]
+fn isolate(_: &mut Command, _: Limits) {}
+
+/// Kills the child and the other processes in its group.
+#[cfg(unix)]
+fn kill_process_group(child: &mut Child) -> io::Result<()> {
+  if unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) } < 0 {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(())
+}
+
+#[cfg(not(unix))]
+fn kill_process_group(child: &mut Child) -> io::Result<()> {
+  child.kill()
+}
+
+
+#[cfg(all(test, unix))]
+fn shell_compiler(run: &str) -> LocalConfig {
+  let mut config = LocalConfig {
+    timeout: Some(1),
+    ..LocalConfig::default()
+  };
+  config.compilers.insert("bash-local".to_owned(),
+                          LocalCompiler {
+                            language: "Bash script".to_owned(),
+                            display_name: None,
+                            version: None,
+                            source: "prog.sh".to_owned(),
+                            compile: None,
+                            run: run.to_owned(),
+                            switches: Vec::new(),
+                          });
+  config
+}
+
+#[cfg(unix)]
+#[test]
+fn test_local_run() {
+  let local = Local::new(shell_compiler("sh prog.sh"));
+  assert_eq!(local.compilers()[0].name, "bash-local");
+
+  let mut param = Parameter::new("read x; echo \"$x $1\"; echo err >&2; exit 3", "bash-local");
+  param.stdin("hello").runtime_option(vec!["world"]);
+  let response = local.compile(param).unwrap();
+  assert_eq!(response.status, 3);
+  assert_eq!(response.program_output, Some("hello world\n".to_owned()));
+  assert_eq!(response.program_error, Some("err\n".to_owned()));
+
+  // The processes spawned by the program are killed with it, and the output is kept.
+  let dir = TempDir::new().unwrap();
+  let log = dir.path().join("alive");
+  let code = format!("echo started; while :; do echo >> '{}'; sleep 0.1; done & wait", log.display());
+  let response = local.compile(Parameter::new(code, "bash-local")).unwrap();
+  assert_eq!(response.signal, Some("Killed".to_owned()));
+  assert_eq!(response.program_output, Some("started\n".to_owned()));
+  let size = || fs::metadata(&log).map(|m| m.len()).unwrap_or(0);
+  thread::sleep(Duration::from_millis(200));
+  let killed = size();
+  assert!(killed > 0);
+  thread::sleep(Duration::from_millis(500));
+  assert_eq!(size(), killed, "the spawned process is still alive");
+
+  assert!(local.compile(Parameter::new("", "gcc-head")).is_err());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_local_limits() {
+  let mut config = shell_compiler("cat /proc/self/limits");
+  config.memory_limit = Some(1 << 30);
+  config.process_limit = Some(1024);
+  let local = Local::new(config);
+  let output = local.compile(Parameter::new("", "bash-local")).unwrap().program_output.unwrap();
+  // The soft limit is the first column after the name.
+  let limit = |name: &str| {
+    output.lines()
+          .find(|line| line.starts_with(name))
+          .and_then(|line| line[name.len()..].split_whitespace().next())
+          .map(ToOwned::to_owned)
+  };
+  assert_eq!(limit("Max cpu time"), Some("1".to_owned()));
+  assert_eq!(limit("Max address space"), Some("1073741824".to_owned()));
+  assert_eq!(limit("Max processes"), Some("1024".to_owned()));
+}
+
+#[test]
+fn test_read_limited() {
+  assert_eq!(read_limited(&b"hello\n"[..], 6), "hello\n");
+  assert_eq!(read_limited(&b"hello\nworld\n"[..], 8), "hello\nwo\nwan: output truncated at 8 bytes\n");
+  assert_eq!(read_limited(&b"hello"[..], 0), "wan: output truncated at 0 bytes\n");
+}
+
+#[test]
+fn test_write_source() {
+  let dir = TempDir::new().unwrap();
+  assert!(write_source(dir.path(), "src/a.h", "").is_ok());
+  assert!(write_source(dir.path(), "../a.h", "").is_err());
+  assert!(write_source(dir.path(), "/tmp/a.h", "").is_err());
+}
@@ -0,0 +1,69 @@
+//! Setup of the logger, which writes the log events of the library to stderr.
+
+use std::cmp;
+use std::env;
+
+use env_logger::LogBuilder;
+use log::{LogLevel, LogLevelFilter, LogRecord};
+use serde_json;
+
+use util;
+
+/// Output format of log events.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+  /// `warning: message`, with the module for the levels below `info`.
+  Text,
+  /// One JSON object per line, with `time`, `level`, `target` and `message`.
+  Json,
+}
+
+/// Initializes the logger with the verbosity given by `-v` (positive) or `-q` (negative).
+///
+/// Warnings and errors are shown by default.  `RUST_LOG`, if set, takes precedence over the
+/// verbosity.
+pub fn init(verbosity: i32, format: Format) -> ::Result<()> {
+  let level = match verbosity {
+    v if v < 0 => LogLevelFilter::Error,
+    0 => LogLevelFilter::Warn,
+    1 => LogLevelFilter::Info,
+    2 => LogLevelFilter::Debug,
+    _ => LogLevelFilter::Trace,
+  };
+
+  let mut builder = LogBuilder::new();
+  match format {
+    Format::Text => builder.format(format_text),
+    Format::Json => builder.format(format_json),
+  };
+  match env::var("RUST_LOG") {
+    Ok(ref filters) if !filters.is_empty() => {
+      builder.parse(filters);
+    }
+    _ => {
+      // Other crates are verbose at the debug level, so they are shown only by `-vvv`.
+      let others = if verbosity >= 3 { LogLevelFilter::Trace } else { LogLevelFilter::Warn };
+      builder.filter(None, cmp::min(others, level)).filter(Some("wan"), level);
+    }
+  }
+  builder.init().map_err(|err| format!("failed to initialize the logger: {}", err))?;
+  Ok(())
+}
+
+fn format_text(record: &LogRecord) -> String {
+  match record.level() {
+    LogLevel::Error => format!("error: {}", record.args()),
+    LogLevel::Warn => format!("warning: {}", record.args()),
+    LogLevel::Info => format!("info: {}", record.args()),
+    level => format!("{}: {}: {}", level.to_string().to_lowercase(), record.target(), record.args()),
+  }
+}
+
+fn format_json(record: &LogRecord) -> String {
+  let mut event = serde_json::Map::new();
+  event.insert("time".to_owned(), util::format_time(::std::time::SystemTime::now()).into());
+  event.insert("level".to_owned(), record.level().to_string().to_lowercase().into());
+  event.insert("target".to_owned(), record.target().into());
+  event.insert("message".to_owned(), record.args().to_string().into());
+  serde_json::Value::Object(event).to_string()
+}
@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use glob;
+use serde_json;
+
+use language::Languages;
+use util;
+use wandbox::CompilerInfo;
+use ResultExt;
+
+/// File names of the project manifest, in JSON or TOML.
+pub const MANIFEST_NAMES: &'static [&'static str] = &["wan.json", "wan.toml"];
+
+/// Project manifest, which describes the programs of a project as named targets.
+#[derive(Debug, Default, Deserialize)]
+pub struct Manifest {
+  /// Name of the target used when none is given.
+  pub default: Option<String>,
+  pub targets: BTreeMap<String, Target>,
+  #[serde(skip_deserializing)]
+  base_dir: PathBuf,
+}
+
+/// A program built from the manifest.
+///
+/// All paths are relative to the directory of the manifest.
+#[derive(Debug, Default, Deserialize)]
+pub struct Target {
+  /// Main source file.
+  pub main: String,
+  /// Supplemental files, or glob patterns of them.
+  #[serde(default)]
+  pub files: Vec<String>,
+  pub compiler: Option<String>,
+  /// Language, used with `version` when `compiler` is omitted.
+  pub language: Option<String>,
+  /// Prefix of the compiler version, e.g. `7.1`.
+  pub version: Option<String>,
+  pub options: Option<String>,
+  /// Names of presets, separated by comma.
+  pub preset: Option<String>,
+  #[serde(rename = "compile-args")]
+  pub compiler_args: Option<String>,
+  #[serde(rename = "runtime-args")]
+  pub runtime_args: Option<String>,
+  /// File passed to the program as standard input.
+  pub stdin: Option<String>,
+}
+
+impl Manifest {
+  /// Finds the manifest in the current directory or its ancestors.
+  pub fn find() -> ::Result<Option<PathBuf>> {
+    let mut dir = env::current_dir()?;
+    loop {
+      if let Some(path) = MANIFEST_NAMES.iter().map(|name| dir.join(name)).find(|path| path.is_file()) {
+        return Ok(Some(path));
+      }
+      if !dir.pop() {
+        return Ok(None);
+      }
+    }
+  }
+
+  /// Loads a manifest, which is parsed as TOML if its extension is `.toml` and as JSON otherwise.
+  pub fn load<P: AsRef<Path>>(path: P) -> ::Result<Manifest> {
+    let path = path.as_ref();
+    let mut text = String::new();
+    File::open(path)
+      .and_then(|mut f| f.read_to_string(&mut text))
+      .chain_err(|| format!("failed to open manifest {}", path.display()))?;
+    let mut manifest = Self::parse(&text, path.extension().map_or(false, |ext| ext == "toml"))
+      .chain_err(|| format!("failed to parse manifest {}", path.display()))?;
+    manifest.base_dir = path.parent().map(ToOwned::to_owned).unwrap_or_default();
+    Ok(manifest)
+  }
+
+  fn parse(text: &str, toml: bool) -> ::Result<Manifest> {
+    if toml {
+      Ok(serde_json::from_value(util::parse_toml(text)?)?)
+    } else {
+      Ok(serde_json::from_str(text)?)
+    }
+  }
+
+  pub fn base_dir(&self) -> &Path {
+    &self.base_dir
+  }
+
+  /// Returns the target of the given name.
+  ///
+  /// When `name` is omitted, the `default` target (or the only one) is returned.
+  pub fn target(&self, name: Option<&str>) -> ::Result<(&str, &Target)> {
+    let name = match name.or(self.default.as_ref().map(String::as_str)) {
+      Some(name) => name,
+      None if self.targets.len() == 1 => self.targets.keys().next().unwrap().as_str(),
+      None => {
+        bail!("target must be specified (available: {})",
+              util::str_join(self.targets.keys(), ", "))
+      }
+    };
+    match self.targets.iter().find(|&(k, _)| k == name) {
+      Some((name, target)) => Ok((name.as_str(), target)),
+      None => {
+        bail!("no target `{}` in manifest (available: {})",
+              name,
+              util::str_join(self.targets.keys(), ", "))
+      }
+    }
+  }
+}
+
+impl Target {
+  pub fn main_path(&self, base_dir: &Path) -> PathBuf {
+    base_dir.join(&self.main)
+  }
+
+  /// Returns the paths of the supplemental files, with glob patterns expanded.
+  pub fn file_paths(&self, base_dir: &Path) -> ::Result<Vec<PathBuf>> {
+    let main = self.main_path(base_dir);
+    let mut paths = Vec::new();
+    for pattern in &self.files {
+      let pattern = base_dir.join(pattern);
+      let pattern = pattern.to_string_lossy();
+      let mut matched = false;
+      for path in glob::glob(&pattern).chain_err(|| format!("invalid pattern `{}`", pattern))? {
+        let path = path.chain_err(|| format!("failed to read files of `{}`", pattern))?;
+        matched = true;
+        if path.is_file() && path != main && !paths.contains(&path) {
+          paths.push(path);
+        }
+      }
+      if !matched {
+        bail!("no files match `{}`", pattern);
+      }
+    }
+    Ok(paths)
+  }
+
+  /// Returns the path of the file passed as standard input.
+  pub fn stdin_path(&self, base_dir: &Path) -> Option<PathBuf> {
+    self.stdin.as_ref().map(|stdin| base_dir.join(stdin))
+  }
+
+  /// Determines the compiler of the target.
+  ///
+  /// Without `compiler`, the language is taken from `language` or the extension of the main
+  /// file, and the compiler is chosen by `version` or the default of the language.
+  pub fn resolve_compiler(&self, languages: &Languages, compilers: &[CompilerInfo]) -> ::Result<String> {
+    if let Some(ref compiler) = self.compiler {
+      return Ok(compiler.clone());
+    }
+
+    let language = match self.language {
+      Some(ref name) => languages.get(name),
+      None => {
+        Path::new(&self.main)
+          .extension()
+          .and_then(|ext| languages.find_by_extension(&ext.to_string_lossy()))
+      }
+    };
+    let language = match language {
+      Some(language) => language,
+      None => bail!("cannot determine the language of `{}`; specify `compiler` or `language`", self.main),
+    };
+
+    let version = match self.version {
+      Some(ref version) => version,
+      None => return Ok(language.compiler.clone()),
+    };
+    let candidates: Vec<_> = compilers.iter().filter(|c| c.language == language.name).collect();
+    match candidates.iter().find(|c| c.version.starts_with(version.as_str())) {
+      Some(compiler) => Ok(compiler.name.clone()),
+      None => {
+        bail!("no compiler of {} has version `{}` (available: {})",
+              language.name,
+              version,
+              util::str_join(candidates.iter().map(|c| format!("{} ({})", c.name, c.version)),
+                             ", "))
+      }
+    }
+  }
+}
+
+
+#[test]
+fn test_manifest_target() {
+  let mut manifest: Manifest = serde_json::from_str(r#"{
+    "targets": {
+      "main": { "main": "main.cpp", "files": ["*.h"], "options": "warning" },
+      "test": { "main": "test.cpp", "compiler": "clang-head", "runtime-args": "-v" }
+    }
+  }"#)
+    .unwrap();
+  assert!(manifest.target(None).is_err());
+  assert!(manifest.target(Some("bench")).is_err());
+  let (name, target) = manifest.target(Some("test")).unwrap();
+  assert_eq!(name, "test");
+  assert_eq!(target.runtime_args, Some("-v".to_owned()));
+
+  manifest.default = Some("main".to_owned());
+  let (name, target) = manifest.target(None).unwrap();
+  assert_eq!(name, "main");
+  assert_eq!(target.files, ["*.h"]);
+}
+
+#[test]
+fn test_manifest_toml() {
+  let manifest = Manifest::parse(r#"
+default = "main"
+
+[targets.main]
+main = "main.cpp"
+files = ["*.h"]
+compile-args = "-DNDEBUG"
+"#,
+                                 true)
+    .unwrap();
+  let (name, target) = manifest.target(None).unwrap();
+  assert_eq!(name, "main");
+  assert_eq!(target.files, ["*.h"]);
+  assert_eq!(target.compiler_args, Some("-DNDEBUG".to_owned()));
+
+  assert!(Manifest::parse(r#"[targets.main]
+files = ["*.h"]"#,
+                          true)
+            .is_err());
+}
+
+#[test]
+fn test_resolve_compiler() {
+  let src = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/list.json"));
+  let compilers: Vec<CompilerInfo> = serde_json::from_str(src).unwrap();
+  let languages = Languages::builtin();
+
+  let mut target = Target {
+
+    main: "main.cpp".to_owned(),
+
+    ..Target::default()
+
+  };
+  assert_eq!(target.resolve_compiler(&languages, &compilers).unwrap(), "gcc-head");
+
+  target.version = Some("4.9".to_owned());
+  assert_eq!(target.resolve_compiler(&languages, &compilers).unwrap(), "gcc-head");
+
+  target.version = Some("7.1".to_owned());
+  assert!(target.resolve_compiler(&languages, &compilers).is_err());
+
+  target.main = "main.unknown".to_owned();
+  assert!(target.resolve_compiler(&languages, &compilers).is_err());
+
+  target.compiler = Some("clang-head".to_owned());
+  assert_eq!(target.resolve_compiler(&languages, &compilers).unwrap(), "clang-head");
+}
@@ -0,0 +1,284 @@
+//! A local stand-in of the Wandbox API, for testing without the network.
+//!
+//! It serves `/api/list.json` from a file or `default_list`, answers `/api/compile.json` with the response of
+//! the first matching rule, and keeps permlinks in memory.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Mutex;
+
+use hyper::method::Method;
+use hyper::server::{self, Handler, Listening, Server};
+use hyper::status::StatusCode;
+use regex::Regex;
+use serde_json;
+
+use http;
+use language::Languages;
+use wandbox::{CompilerInfo, Parameter, Response};
+use ResultExt;
+
+/// Returns the compiler list served by default, which has the default compiler of each built-in
+/// language, without switches.
+pub fn default_list() -> String {
+  let mut compilers: Vec<CompilerInfo> = Vec::new();
+  for language in Languages::builtin().iter() {
+    if compilers.iter().any(|c| c.name == language.compiler) {
+      continue;
+    }
+    compilers.push(CompilerInfo {
+                     name: language.compiler.clone(),
+                     version: "head".to_owned(),
+                     language: language.name.clone(),
+                     display_name: language.compiler.clone(),
+                     compiler_option_raw: true,
+                     runtime_option_raw: true,
+                     display_compile_command: language.compiler.clone(),
+                     switches: Vec::new(),
+                   });
+  }
+  serde_json::to_string(&compilers).unwrap()
+}
+
+/// A scripted response, returned when the compiler and the code of a request match the patterns.
+///
+/// The pattern of `compiler` must match the whole name, and that of `code` any part of the code.
+#[derive(Debug, Deserialize)]
+pub struct Rule {
+  #[serde(default)]
+  pub compiler: Option<String>,
+  #[serde(default)]
+  pub code: Option<String>,
+  pub response: Response,
+}
+
+#[derive(Debug)]
+struct Matcher {
+  compiler: Option<Regex>,
+  code: Option<Regex>,
+  response: Response,
+}
+
+impl Matcher {
+  fn matches(&self, parameter: &Parameter) -> bool {
+    self.compiler.as_ref().map_or(true, |re| re.is_match(&parameter.compiler)) &&
+    self.code.as_ref().map_or(true, |re| re.is_match(&parameter.code))
+  }
+}
+
+#[derive(Serialize)]
+struct Permlink<'a> {
+  parameter: &'a Parameter,
+  result: &'a Response,
+}
+
+#[derive(Debug)]
+pub struct MockServer {
+  list: String,
+  rules: Vec<Matcher>,
+  permlinks: Mutex<HashMap<String, String>>,
+}
+
+impl MockServer {
+  /// Creates a server which serves the compiler list of the given JSON.
+  pub fn new<S: Into<String>>(list: S) -> ::Result<MockServer> {
+    let list = list.into();
+    let _: Vec<CompilerInfo> = serde_json::from_str(&list).chain_err(|| "invalid compiler list")?;
+    Ok(MockServer {
+      list: list,
+      rules: Vec::new(),
+      permlinks: Mutex::new(HashMap::new()),
+    })
+  }
+
+  /// Creates a server which serves the compiler list in the file.
+  pub fn from_file<P: AsRef<Path>>(path: P) -> ::Result<MockServer> {
+    let path = path.as_ref();
+    let mut list = String::new();
+    File::open(path)
+      .and_then(|mut f| f.read_to_string(&mut list))
+      .chain_err(|| format!("failed to read {}", path.display()))?;
+    Self::new(list).chain_err(|| format!("failed to load {}", path.display()))
+  }
+
+  /// Adds a rule, which is tried after the ones added before.
+  pub fn rule(&mut self, rule: Rule) -> ::Result<&mut Self> {
+    let compiler = match rule.compiler {
+      Some(ref pattern) => Some(Regex::new(&format!("^(?:{})$", pattern))?),
+      None => None,
+    };
+    let code = match rule.code {
+      Some(ref pattern) => Some(Regex::new(pattern)?),
+      None => None,
+    };
+    self.rules.push(Matcher {
+      compiler: compiler,
+      code: code,
+      response: rule.response,
+    });
+    Ok(self)
+  }
+
+  /// Adds the rules in a JSON file, which contains an array of rules.
+  pub fn load_rules<P: AsRef<Path>>(&mut self, path: P) -> ::Result<&mut Self> {
+    let path = path.as_ref();
+    let file = File::open(path).chain_err(|| format!("failed to open {}", path.display()))?;
+    let rules: Vec<Rule> = serde_json::from_reader(file).chain_err(|| format!("failed to parse {}", path.display()))?;
+    for rule in rules {
+      self.rule(rule).chain_err(|| format!("invalid rule in {}", path.display()))?;
+    }
+    Ok(self)
+  }
+
+  /// Starts the server in background threads. Use port 0 to choose a free port.
+  pub fn start(self, addr: &str) -> ::Result<Running> {
+    let listening = Server::http(addr)?.handle(self)?;
+    info!("mock server is listening on {}", listening.socket);
+    Ok(Running { listening: Some(listening) })
+  }
+
+  fn compile(&self, body: &str, host: Option<&str>) -> (StatusCode, String) {
+    let parameter: Parameter = match serde_json::from_str(body) {
+      Ok(parameter) => parameter,
+      Err(err) => return (StatusCode::BadRequest, format!("invalid request: {}", err)),
+    };
+    let mut response = match self.rules.iter().find(|m| m.matches(&parameter)) {
+      Some(matcher) => merge_messages(matcher.response.clone()),
+      None => return (StatusCode::BadRequest, format!("no rule matches the request for `{}`", parameter.compiler)),
+    };
+
+    if parameter.save == Some(true) {
+      let mut permlinks = self.permlinks.lock().unwrap();
+      let link = format!("mock{:06}", permlinks.len() + 1);
+      response.url = host.map(|host| format!("http://{}/permlink/{}", host, link));
+      response.permlink = Some(link.clone());
+      let json = serde_json::to_string(&Permlink {
+                                         parameter: &parameter,
+                                         result: &response,
+                                       })
+        .unwrap();
+      permlinks.insert(link, json);
+    }
+    (StatusCode::Ok, serde_json::to_string(&response).unwrap())
+  }
+
+  fn route(&self, method: &Method, path: &str, body: &str, host: Option<&str>) -> (StatusCode, String) {
+    match (method, path) {
+      (&Method::Get, "/api/list.json") => (StatusCode::Ok, self.list.clone()),
+      (&Method::Post, "/api/compile.json") => self.compile(body, host),
+      (&Method::Get, path) if path.starts_with("/api/permlink/") => {
+        match self.permlinks.lock().unwrap().get(&path["/api/permlink/".len()..]) {
+          Some(json) => (StatusCode::Ok, json.clone()),
+          None => (StatusCode::NotFound, "permlink is not found".to_owned()),
+        }
+      }
+      _ => (StatusCode::NotFound, "not found".to_owned()),
+    }
+  }
+}
+
+/// Fills the messages, which Wandbox makes by merging the outputs and the errors.
+fn merge_messages(mut response: Response) -> Response {
+  if response.compiler_message.is_none() && (response.compiler_output.is_some() || response.compiler_error.is_some()) {
+    response.compiler_message = Some(format!("{}{}",
+                                             response.compiler_output.as_ref().map_or("", String::as_str),
+                                             response.compiler_error.as_ref().map_or("", String::as_str)));
+  }
+  if response.program_message.is_none() && (response.program_output.is_some() || response.program_error.is_some()) {
+    response.program_message = Some(format!("{}{}",
+                                            response.program_output.as_ref().map_or("", String::as_str),
+                                            response.program_error.as_ref().map_or("", String::as_str)));
+  }
+  response
+}
+
+impl Handler for MockServer {
+  fn handle(&self, req: server::Request, res: server::Response) {
+    let (req, result) = http::Request::read(req);
+    let (status, content) = match result {
+      Ok(()) => self.route(&req.method, &req.path, &req.body, req.host()),
+      Err(error) => error,
+    };
+    info!("mock: {} {} -> {}", req.method, req.path, status);
+    http::send(res, status, &content);
+  }
+}
+
+/// A running mock server.
+///
+/// Dropping it stops accepting the requests only when the process exits, because hyper cannot
+/// stop the server.
+pub struct Running {
+  listening: Option<Listening>,
+}
+
+impl Running {
+  /// Returns the URL of the server, to be given to `Wandbox::new`.
+  pub fn url(&self) -> String {
+    format!("http://{}", self.listening.as_ref().unwrap().socket)
+  }
+
+  /// Blocks the current thread while the server is running.
+  pub fn wait(mut self) {
+    // `Listening` joins the threads of the server when dropped.
+    self.listening.take();
+  }
+}
+
+impl Drop for Running {
+  fn drop(&mut self) {
+    if let Some(mut listening) = self.listening.take() {
+      let _ = listening.close();
+    }
+  }
+}
+
+impl ::std::fmt::Debug for Running {
+  fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+    write!(f, "Running {{ url: {} }}", self.url())
+  }
+}
+
+
+#[test]
+fn test_mock_server() {
+  use wandbox::Wandbox;
+
+  let mut server = MockServer::new(default_list()).unwrap();
+  server.rule(serde_json::from_str(r#"{
+      "compiler": "gcc-.*",
+      "code": "int main",
+      "response": { "status": 0, "program_message": "hello" }
+    }"#)
+              .unwrap())
+        .unwrap();
+  server.rule(serde_json::from_str(r#"{
+      "code": "warning",
+      "response": { "status": 0, "compiler_error": "warning\n", "program_output": "hi\n", "program_error": "!\n" }
+    }"#)
+              .unwrap())
+        .unwrap();
+  let running = server.start("127.0.0.1:0").unwrap();
+  let wandbox = Wandbox::new(Some(running.url()));
+
+  let compilers = wandbox.get_compiler_info().unwrap();
+  assert!(compilers.iter().any(|c| c.name == "gcc-head" && c.language == "C++"));
+
+  let response = wandbox.submit(Parameter::new("#warning", "clang-head")).unwrap();
+  assert_eq!(response.compiler_message, Some("warning\n".to_owned()));
+  assert_eq!(response.program_message, Some("hi\n!\n".to_owned()));
+
+  let mut parameter = Parameter::new("int main() {}", "gcc-head");
+  parameter.save_permlink(true);
+  let response = wandbox.submit(parameter).unwrap();
+  assert_eq!(response.program_message, Some("hello".to_owned()));
+  let link = response.permlink.unwrap();
+  assert!(wandbox.get_permlink(&link).unwrap().contains("int main() {}"));
+
+  assert!(wandbox.submit(Parameter::new("int main() {}", "clang-head")).is_err());
+  #[allow(deprecated)]
+  let response = wandbox.compile(Parameter::new("int main() {}", "gcc-head"), true).unwrap();
+  assert_eq!(response.program_message, Some("hello".to_owned()));
+}
@@ -0,0 +1,396 @@
+use util::{self, Either};
+use wandbox::{CompilerInfo, CompilerOption, CompilerSwitchMultiOptions, Parameter};
+
+/// State of a switch within an `OptionSet`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Selection<'a> {
+  Flag(bool),
+  Choice(Option<&'a CompilerOption>),
+}
+
+/// Shortcut for a commonly used switch, resolved against the compiler's catalog.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shortcut<'a> {
+  /// Language standard, e.g. `c++17` or `gnu++1y`.
+  Std(&'a str),
+  /// Version of Boost, e.g. `1.64` (or `nothing`).
+  Boost(&'a str),
+  Warnings,
+  Optimize,
+  Sprout,
+}
+
+/// Set of options validated against the switches of a compiler.
+///
+/// A fresh set holds the defaults of the compiler; named options are then enabled (or disabled
+/// with a `no-` prefix) on top of them.
+#[derive(Debug, Clone)]
+pub struct OptionSet<'a> {
+  info: &'a CompilerInfo,
+  selections: Vec<Selection<'a>>,
+  explicit: Vec<Option<&'a str>>,
+}
+
+impl<'a> OptionSet<'a> {
+  pub fn new(info: &'a CompilerInfo) -> OptionSet<'a> {
+    let selections = info.switches
+                         .iter()
+                         .map(|switch| match *switch {
+                                Either::Left(ref switch) => Selection::Flag(switch.default),
+                                Either::Right(ref switch) => {
+                                  Selection::Choice(switch.options.iter().find(|o| o.name == switch.default))
+                                }
+                              })
+                         .collect();
+    OptionSet {
+      info: info,
+      selections: selections,
+      explicit: vec![None; info.switches.len()],
+    }
+  }
+
+  pub fn compiler(&self) -> &'a CompilerInfo {
+    self.info
+  }
+
+  /// Applies the options of several sources in order of precedence, e.g. directives, then
+  /// `--options`. See `start_layer`.
+  pub fn apply_layers<S: AsRef<str>>(&mut self, layers: &[S]) -> ::Result<()> {
+    for layer in layers {
+      self.start_layer();
+      self.apply(layer.as_ref())?;
+    }
+    Ok(())
+  }
+
+  /// Lets the following options replace the choices made so far in the same group.
+  ///
+  /// Conflicting choices are still rejected within a source, e.g. `c++98,gnu++1y` in `--options`.
+  pub fn start_layer(&mut self) {
+    for explicit in &mut self.explicit {
+      *explicit = None;
+    }
+  }
+
+  /// Applies a comma-separated list of option names, e.g. `"warning,c++98,no-sprout"`.
+  pub fn apply(&mut self, options: &str) -> ::Result<()> {
+    for name in options.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+      if name.starts_with("no-") && self.find(name).is_none() {
+        self.disable(&name[3..])?;
+      } else {
+        self.enable(name)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Enables the option of the given name.
+  pub fn enable(&mut self, name: &str) -> ::Result<()> {
+    let (index, name, option) = match self.find(name) {
+      Some(found) => found,
+      None => bail!(self.unknown_option(name)),
+    };
+
+    if let Some(previous) = self.explicit[index] {
+      if previous != name {
+        bail!("options `{}` and `{}` cannot be used together with compiler `{}`",
+              previous,
+              name,
+              self.info.name);
+      }
+    }
+    self.explicit[index] = Some(name);
+
+    self.selections[index] = match option {
+      Some(option) => Selection::Choice(Some(option)),
+      None => Selection::Flag(true),
+    };
+    Ok(())
+  }
+
+  /// Enables the switch or option which a shortcut stands for.
+  pub fn apply_shortcut(&mut self, shortcut: Shortcut) -> ::Result<()> {
+    let name = match shortcut {
+      Shortcut::Std(std) => self.find_std(std)?,
+      Shortcut::Boost(version) => self.find_boost(version)?,
+      Shortcut::Warnings => "warning".to_owned(),
+      Shortcut::Optimize => "optimize".to_owned(),
+      Shortcut::Sprout => "sprout".to_owned(),
+    };
+    if self.find(&name).is_none() {
+      bail!("compiler `{}` has no switch `{}`", self.info.name, name);
+    }
+    self.enable(&name)
+  }
+
+  fn find_std(&self, std: &str) -> ::Result<String> {
+    let flag = format!("-std={}", std);
+    let group = match self.find_group(|o| o.display_flags.contains("-std=")) {
+      Some(group) => group,
+      None => bail!("compiler `{}` has no switch for the language standard", self.info.name),
+    };
+    match group.options
+               .iter()
+               .find(|o| o.name == std || o.display_flags.split_whitespace().any(|f| f == flag)) {
+      Some(option) => Ok(option.name.clone()),
+      None => {
+        bail!("compiler `{}` does not support standard `{}` (available: {})",
+              self.info.name,
+              std,
+              util::str_join(group.options.iter().map(|o| o.name.as_str()), ", "))
+      }
+    }
+  }
+
+  fn find_boost(&self, version: &str) -> ::Result<String> {
+    let group = match self.find_group(|o| o.name.starts_with("boost-")) {
+      Some(group) => group,
+      None => bail!("compiler `{}` has no switch for Boost", self.info.name),
+    };
+    let name = format!("boost-{}", version);
+    let prefixes = [format!("{}.", name), format!("{}-", name)];
+    match group.options
+               .iter()
+               .find(|o| o.name == name)
+               .or_else(|| group.options.iter().find(|o| prefixes.iter().any(|p| o.name.starts_with(p.as_str())))) {
+      Some(option) => Ok(option.name.clone()),
+      None => {
+        bail!("compiler `{}` does not provide Boost `{}` (available: {})",
+              self.info.name,
+              version,
+              util::str_join(group.options.iter().map(|o| o.name.trim_left_matches("boost-")), ", "))
+      }
+    }
+  }
+
+  fn find_group<F>(&self, pred: F) -> Option<&'a CompilerSwitchMultiOptions>
+    where F: Fn(&CompilerOption) -> bool
+  {
+    let info: &'a CompilerInfo = self.info;
+    info.switches
+        .iter()
+        .filter_map(|switch| match *switch {
+                      Either::Right(ref switch) => Some(switch),
+                      Either::Left(_) => None,
+                    })
+        .find(|switch| switch.options.iter().any(&pred))
+  }
+
+  /// Disables the switch of the given name.
+  ///
+  /// Only on/off switches can be disabled; a group of choices always keeps one of them.
+  pub fn disable(&mut self, name: &str) -> ::Result<()> {
+    match self.find(name) {
+      Some((index, _, None)) => {
+        self.selections[index] = Selection::Flag(false);
+        Ok(())
+      }
+      Some((_, _, Some(_))) => bail!("option `{}` is one of a group of choices and cannot be disabled", name),
+      None => bail!(self.unknown_option(name)),
+    }
+  }
+
+  /// Returns the names of all enabled options, in the order of the compiler's switches.
+  pub fn names(&self) -> Vec<&'a str> {
+    self.info
+        .switches
+        .iter()
+        .zip(self.selections.iter())
+        .filter_map(|(switch, selection)| match (switch, *selection) {
+                      (&Either::Left(ref switch), Selection::Flag(true)) => Some(switch.name.as_str()),
+                      (_, Selection::Choice(Some(option))) => Some(option.name.as_str()),
+                      _ => None,
+                    })
+        .collect()
+  }
+
+  /// Returns the compiler flags of all enabled options, as shown by `display-flags`.
+  pub fn display_flags(&self) -> Vec<&'a str> {
+    self.info
+        .switches
+        .iter()
+        .zip(self.selections.iter())
+        .filter_map(|(switch, selection)| match (switch, *selection) {
+                      (&Either::Left(ref switch), Selection::Flag(true)) => Some(switch.display_flags.as_str()),
+                      (_, Selection::Choice(Some(option))) => Some(option.display_flags.as_str()),
+                      _ => None,
+                    })
+        .filter(|flags| !flags.is_empty())
+        .collect()
+  }
+
+  /// Returns the value of `options` to be sent to Wandbox.
+  pub fn to_options_string(&self) -> String {
+    util::str_join(self.names(), ",")
+  }
+
+  fn find(&self, name: &str) -> Option<(usize, &'a str, Option<&'a CompilerOption>)> {
+    let info: &'a CompilerInfo = self.info;
+    for (index, switch) in info.switches.iter().enumerate() {
+      match *switch {
+        Either::Left(ref switch) => {
+          if switch.name == name {
+            return Some((index, switch.name.as_str(), None));
+          }
+        }
+        Either::Right(ref switch) => {
+          if let Some(option) = switch.options.iter().find(|o| o.name == name) {
+            return Some((index, option.name.as_str(), Some(option)));
+          }
+        }
+      }
+    }
+    None
+  }
+
+  fn unknown_option(&self, name: &str) -> String {
+    format!("compiler `{}` has no option `{}` (available: {})",
+            self.info.name,
+            name,
+            util::str_join(available_options(self.info), ", "))
+  }
+}
+
+/// Returns the names of all options the compiler accepts.
+pub fn available_options(info: &CompilerInfo) -> Vec<&str> {
+  let mut names = Vec::new();
+  for switch in &info.switches {
+    match *switch {
+      Either::Left(ref switch) => names.push(switch.name.as_str()),
+      Either::Right(ref switch) => names.extend(switch.options.iter().map(|o| o.name.as_str())),
+    }
+  }
+  names
+}
+
+/// Checks whether the raw arguments in `param` are accepted by the compiler.
+///
+/// Returns a message for each kind of raw argument which Wandbox would silently ignore.
+pub fn check_raw_options(info: &CompilerInfo, param: &Parameter) -> Vec<String> {
+  let mut messages = Vec::new();
+  if param.compiler_option_raw.is_some() && !info.compiler_option_raw {
+    messages.push(format!("compiler `{}` does not accept raw compiler arguments", info.name));
+  }
+  if param.runtime_option_raw.is_some() && !info.runtime_option_raw {
+    messages.push(format!("compiler `{}` does not accept raw runtime arguments", info.name));
+  }
+  messages
+}
+
+
+/// Returns the command line Wandbox will use to compile the code.
+///
+/// This is the `display-compile-command` of the compiler followed by the flags of the enabled
+/// options and the raw compiler arguments.
+pub fn compile_command(options: &OptionSet, param: &Parameter) -> String {
+  let mut command = options.compiler().display_compile_command.clone();
+  for flags in options.display_flags() {
+    command.push(' ');
+    command.push_str(flags);
+  }
+  if let Some(ref option_raw) = param.compiler_option_raw {
+    if options.compiler().compiler_option_raw && !option_raw.is_empty() {
+      command.push(' ');
+      command.push_str(&util::shell_join(option_raw.split("\n")));
+    }
+  }
+  command
+}
+
+
+#[cfg(test)]
+fn load_compiler_info() -> Vec<CompilerInfo> {
+  let src = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/list.json"));
+  ::serde_json::from_str(src).unwrap()
+}
+
+#[test]
+fn test_option_set_defaults() {
+  let info = load_compiler_info();
+  let options = OptionSet::new(&info[0]);
+  assert_eq!(options.names(), ["warning", "boost-1.55", "sprout", "gnu++1y"]);
+  assert_eq!(options.to_options_string(), "warning,boost-1.55,sprout,gnu++1y");
+}
+
+#[test]
+fn test_option_set_apply() {
+  let info = load_compiler_info();
+  let mut options = OptionSet::new(&info[0]);
+  options.apply("optimize,c++98,no-sprout").unwrap();
+  assert_eq!(options.names(), ["warning", "optimize", "boost-1.55", "c++98"]);
+  assert_eq!(options.display_flags(),
+             ["-Wall -Wextra", "-O2 -march=native", "-I/usr/local/boost-1.55.0/include", "-std=c++98 -pedantic"]);
+}
+
+#[test]
+fn test_option_set_errors() {
+  let info = load_compiler_info();
+  assert!(OptionSet::new(&info[0]).apply("c++17").is_err());
+  assert!(OptionSet::new(&info[0]).apply("c++98,gnu++1y").is_err());
+  assert!(OptionSet::new(&info[0]).apply("no-boost-1.47").is_err());
+  assert!(OptionSet::new(&info[0]).apply("c++98,c++98").is_ok());
+}
+
+#[test]
+fn test_option_set_shortcuts() {
+  let info = load_compiler_info();
+  let mut options = OptionSet::new(&info[0]);
+  options.apply_shortcut(Shortcut::Std("c++98")).unwrap();
+  options.apply_shortcut(Shortcut::Boost("1.47")).unwrap();
+  options.apply_shortcut(Shortcut::Optimize).unwrap();
+  assert_eq!(options.names(), ["warning", "optimize", "boost-1.47", "sprout", "c++98"]);
+
+  let mut options = OptionSet::new(&info[0]);
+  options.apply_shortcut(Shortcut::Boost("nothing")).unwrap();
+  assert!(options.apply_shortcut(Shortcut::Std("c++17")).is_err());
+  assert!(options.apply_shortcut(Shortcut::Boost("1.64")).is_err());
+
+  // A group may have an option whose name is shorter than the prefix.
+  let mut info = info[0].clone();
+  for switch in &mut info.switches {
+    if let Either::Right(ref mut switch) = *switch {
+      if switch.options[0].name.starts_with("boost-") {
+        switch.options[0].name = "none".to_owned();
+      }
+    }
+  }
+  assert!(OptionSet::new(&info).apply_shortcut(Shortcut::Boost("1.64")).is_err());
+}
+
+#[test]
+fn test_option_set_layers() {
+  let info = load_compiler_info();
+  let mut options = OptionSet::new(&info[0]);
+  options.apply_layers(&["c++98,no-warning", "gnu++1y"]).unwrap();
+  assert_eq!(options.names(), ["boost-1.55", "sprout", "gnu++1y"]);
+  assert!(options.apply_layers(&["c++98,gnu++1y"]).is_err());
+
+  // Shortcuts override the options of the directives.
+  let mut options = OptionSet::new(&info[0]);
+  options.apply_layers(&["gnu++1y"]).unwrap();
+  options.start_layer();
+  options.apply_shortcut(Shortcut::Std("c++98")).unwrap();
+  assert_eq!(options.names(), ["warning", "boost-1.55", "sprout", "c++98"]);
+}
+
+#[test]
+fn test_compile_command() {
+  let info = load_compiler_info();
+  let mut options = OptionSet::new(&info[0]);
+  options.apply("no-warning,no-sprout,boost-nothing").unwrap();
+  let mut param = Parameter::new("", "gcc-head");
+  param.compiler_option(vec!["-DMESSAGE=\"hello world\"", "-O0"]);
+  assert_eq!(compile_command(&options, &param),
+             r#"g++ prog.cc -std=gnu++1y '-DMESSAGE="hello world"' -O0"#);
+}
+
+#[test]
+fn test_check_raw_options() {
+  let info = load_compiler_info();
+  let mut param = Parameter::new("", "gcc-head");
+  assert!(check_raw_options(&info[0], &param).is_empty());
+  param.compiler_option(vec!["-DNDEBUG"]);
+  assert!(check_raw_options(&info[0], &param).is_empty());
+  param.runtime_option(vec!["foo"]);
+  assert_eq!(check_raw_options(&info[0], &param).len(), 1);
+}
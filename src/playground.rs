@@ -0,0 +1,364 @@
+//! A backend which runs Rust code on the Rust Playground (play.rust-lang.org), where the popular
+//! crates are available.
+//!
+//! The compilers `rust-stable`, `rust-beta` and `rust-nightly` select the channel, and the options
+//! select the edition, the release mode, whether the tests are run and the crate type. Permlinks
+//! are gists, which keep only the code.
+
+use std::io::{self, Read};
+
+use hyper::method::Method;
+use regex::Regex;
+use serde_json::{self, Value};
+
+use backend::Backend;
+use config::Config;
+use http;
+use options::OptionSet;
+use util::Either;
+use wandbox::{CompilerInfo, CompilerOption, CompilerSwitch, CompilerSwitchMultiOptions, Parameter, Response};
+use ResultExt;
+
+pub const PLAYGROUND_URL: &'static str = "https://play.rust-lang.org";
+
+const CHANNELS: &'static [&'static str] = &["stable", "beta", "nightly"];
+const EDITIONS: &'static [&'static str] = &["2015", "2018", "2021", "2024"];
+const DEFAULT_EDITION: &'static str = "2021";
+
+lazy_static!{
+  /// `fn main` at the start of a line, which is not in a comment or a string in most code.
+  static ref MAIN: Regex = Regex::new(r"(?m)^\s*(?:pub\s+)?fn\s+main\s*\(").unwrap();
+}
+
+#[derive(Serialize)]
+struct ExecuteRequest<'a> {
+  channel: &'a str,
+  mode: &'a str,
+  edition: &'a str,
+  #[serde(rename = "crateType")]
+  crate_type: &'a str,
+  tests: bool,
+  backtrace: bool,
+  code: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ExecuteResponse {
+  success: bool,
+  #[serde(default, rename = "exitDetail")]
+  exit_detail: String,
+  #[serde(default)]
+  stdout: String,
+  #[serde(default)]
+  stderr: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Gist {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  id: Option<String>,
+  code: String,
+}
+
+pub struct Playground {
+  http: http::Client,
+}
+
+impl Playground {
+  pub fn new(url: Option<String>) -> Playground {
+    Playground { http: http::Client::new(vec![url.unwrap_or_else(|| PLAYGROUND_URL.to_owned())]) }
+  }
+
+  pub fn from_config(config: &Config) -> Playground {
+    Playground { http: http::Client::for_backend(config, "playground", PLAYGROUND_URL) }
+  }
+
+  /// Returns a compiler for each channel, with the version of rustc.
+  pub fn compilers(&self) -> ::Result<Vec<CompilerInfo>> {
+    let versions: Value = self.send(Method::Get, "/meta/versions", None)?;
+    Ok(CHANNELS.iter()
+               .map(|channel| {
+                      let version = versions[*channel]["rustc"]["version"].as_str().unwrap_or("");
+                      compiler_info(channel, version)
+                    })
+               .collect())
+  }
+
+  fn send<T: ::serde::Deserialize>(&self, method: Method, path: &str, body: Option<&str>) -> ::Result<T> {
+    let (status, body) = self.http.forward(method, path, body)?;
+    let url = format!("{}{}", self.http.url(), path);
+    if !status.is_success() {
+      bail!(::ErrorKind::HttpStatus(url, status.to_string()));
+    }
+    serde_json::from_str(&body).chain_err(|| format!("invalid response from {}", url))
+  }
+}
+
+impl Backend for Playground {
+  fn get_compiler_info_raw(&self) -> ::Result<Box<Read>> {
+    let json = serde_json::to_string(&self.compilers()?)?;
+    Ok(Box::new(io::Cursor::new(json.into_bytes())))
+  }
+
+  fn get_compiler_info(&self) -> ::Result<Vec<CompilerInfo>> {
+    self.compilers()
+  }
+
+  fn compile(&self, param: Parameter) -> ::Result<Response> {
+    let channel = match CHANNELS.iter().find(|c| param.compiler == format!("rust-{}", c)) {
+      Some(channel) => *channel,
+      None => bail!(::ErrorKind::UnknownCompiler(param.compiler.clone())),
+    };
+    if param.codes.as_ref().map_or(false, |codes| !codes.is_empty()) {
+      bail!("the Rust Playground runs a single file; put the modules inline with `mod name {{ ... }}`");
+    }
+    let ignored = [("standard input", &param.stdin),
+                   ("compiler arguments", &param.compiler_option_raw),
+                   ("runtime arguments", &param.runtime_option_raw)];
+    for &(name, value) in &ignored {
+      if value.as_ref().map_or(false, |value| !value.is_empty()) {
+        warn!("{} are not supported by the Rust Playground, and ignored", name);
+      }
+    }
+
+    let info = compiler_info(channel, "");
+    let mut options = OptionSet::new(&info);
+    if let Some(ref names) = param.options {
+      options.apply(names)?;
+    }
+    let names = options.names();
+    let edition = names.iter().find(|name| name.starts_with("edition")).map_or(DEFAULT_EDITION, |name| &name[7..]);
+    let tests = names.contains(&"test");
+    let crate_type = match (names.contains(&"bin"), names.contains(&"lib")) {
+      (true, true) => bail!("options `bin` and `lib` cannot be used together"),
+      (true, false) => "bin",
+      (false, true) => "lib",
+      (false, false) => if MAIN.is_match(&param.code) { "bin" } else { "lib" },
+    };
+    let request = ExecuteRequest {
+      channel: channel,
+      mode: if names.contains(&"optimize") { "release" } else { "debug" },
+      edition: edition,
+      crate_type: crate_type,
+      tests: tests,
+      backtrace: false,
+      code: &param.code,
+    };
+    let body = serde_json::to_string(&request)?;
+    let result: ExecuteResponse = self.send(Method::Post, "/execute", Some(&body))?;
+    let mut response = to_response(result);
+
+    if param.save == Some(true) {
+      let gist = Gist {
+        id: None,
+        code: param.code.clone(),
+      };
+      let gist: Gist = self.send(Method::Post, "/meta/gist", Some(&serde_json::to_string(&gist)?))?;
+      let id = match gist.id {
+        Some(id) => id,
+        None => bail!("the Rust Playground did not return the id of the gist"),
+      };
+      response.url = Some(format!("{}/?version={}&mode={}&edition={}&gist={}",
+                                  self.http.url(),
+                                  channel,
+                                  request.mode,
+                                  edition,
+                                  id));
+      response.permlink = Some(id);
+    }
+    Ok(response)
+  }
+
+  /// Fails, since a gist has the code but neither the channel, the options nor the result.
+  fn get_permlink(&self, link: &str) -> ::Result<String> {
+    bail!("gists of the Rust Playground have no result; see {} for the code",
+          self.permlink_url(link).unwrap())
+  }
+
+  fn permlink_url(&self, link: &str) -> Option<String> {
+    Some(format!("{}/?gist={}", self.http.url(), link))
+  }
+}
+
+fn compiler_info(channel: &str, version: &str) -> CompilerInfo {
+  let switch = |name: &str, display_name: &str| {
+    Either::Left(CompilerSwitch {
+                   default: false,
+                   name: name.to_owned(),
+                   display_name: display_name.to_owned(),
+                   display_flags: String::new(),
+                 })
+  };
+  let editions = EDITIONS.iter()
+                         .map(|edition| {
+                                CompilerOption {
+                                  name: format!("edition{}", edition),
+                                  display_name: format!("Edition {}", edition),
+                                  display_flags: format!("--edition={}", edition),
+                                }
+                              })
+                         .collect();
+  CompilerInfo {
+    name: format!("rust-{}", channel),
+    version: version.to_owned(),
+    language: "Rust".to_owned(),
+    display_name: format!("rustc {}", channel),
+    compiler_option_raw: false,
+    runtime_option_raw: false,
+    display_compile_command: "cargo run".to_owned(),
+    switches: vec![Either::Right(CompilerSwitchMultiOptions {
+                                   default: format!("edition{}", DEFAULT_EDITION),
+                                   options: editions,
+                                 }),
+                   switch("optimize", "Release mode"),
+                   switch("test", "Run the tests"),
+                   switch("bin", "Binary crate"),
+                   switch("lib", "Library crate")],
+  }
+}
+
+/// Converts the result, whose standard error has the messages of Cargo followed by the one of the
+/// program.
+fn to_response(result: ExecuteResponse) -> Response {
+  let mut response = Response::default();
+  let (build, run) = match result.stderr.find("     Running ") {
+    Some(index) => {
+      let end = result.stderr[index..].find('\n').map_or(result.stderr.len(), |i| index + i + 1);
+      (&result.stderr[..end], Some(&result.stderr[end..]))
+    }
+    None => (result.stderr.as_str(), None),
+  };
+  response.compiler_message = Some(build.to_owned());
+  response.compiler_error = Some(build.to_owned());
+
+  let detail = result.exit_detail.trim();
+  response.status = if result.success {
+    0
+  } else if detail.starts_with("Exited with status ") {
+    detail["Exited with status ".len()..].parse().unwrap_or(1)
+  } else {
+    1
+  };
+  if detail.starts_with("Exited with signal ") {
+    response.signal = Some(detail["Exited with signal ".len()..].to_owned());
+  }
+  if let Some(run) = run {
+    response.program_message = Some(format!("{}{}", result.stdout, run));
+    response.program_output = Some(result.stdout);
+    response.program_error = Some(run.to_owned());
+  }
+  response
+}
+
+
+#[test]
+fn test_from_config() {
+  use std::collections::HashMap;
+  use std::fs::File;
+  use std::io::Write;
+  use config::LoadOptions;
+  use util::TempDir;
+
+  let dir = TempDir::new().unwrap();
+  // The configuration is the project one, and neither the environment nor the user configuration of the process is
+  // used.
+  let url = |text: &str| {
+    File::create(dir.path().join(".wan.json")).and_then(|mut f| f.write_all(text.as_bytes())).unwrap();
+    let mut env = HashMap::new();
+    env.insert("XDG_CONFIG_HOME".to_owned(), dir.path().display().to_string());
+    let config = Config::load_with(&LoadOptions {
+                                     dir: Some(dir.path().to_owned()),
+                                     env: Some(env),
+                                     ..LoadOptions::default()
+                                   })
+      .unwrap();
+    Playground::from_config(&config).http.url().to_owned()
+  };
+  assert_eq!(url(r#"{ "backend": "playground" }"#), PLAYGROUND_URL);
+  assert_eq!(url(r#"{ "backend": "playground", "url": "http://127.0.0.1:10240/" }"#), "http://127.0.0.1:10240");
+  // The top-level `url` is of Wandbox, and the profile selecting the backend falls back to the Rust Playground.
+  let text = r#"{
+    "url": "http://127.0.0.1:3500",
+    "profile": "rust",
+    "profiles": { "rust": { "backend": "playground" } }
+  }"#;
+  assert_eq!(url(text), PLAYGROUND_URL);
+}
+
+#[test]
+fn test_to_response() {
+  let response = to_response(ExecuteResponse {
+                               success: false,
+                               exit_detail: "Exited with status 101".to_owned(),
+                               stdout: "hi\n".to_owned(),
+                               stderr: "   Compiling playground v0.0.1 (/playground)\n     Running \
+                                        `target/debug/playground`\nthread 'main' panicked\n"
+                                 .to_owned(),
+                             });
+  assert_eq!(response.status, 101);
+  assert_eq!(response.program_error, Some("thread 'main' panicked\n".to_owned()));
+  assert!(response.compiler_message.unwrap().ends_with("Running `target/debug/playground`\n"));
+
+  let response = to_response(ExecuteResponse {
+                               success: false,
+                               exit_detail: String::new(),
+                               stdout: String::new(),
+                               stderr: "error[E0425]: cannot find value `x`\n".to_owned(),
+                             });
+  assert_eq!((response.status, response.program_message), (1, None));
+}
+
+#[test]
+fn test_playground() {
+  use hyper::server::{self, Server};
+  use hyper::status::StatusCode;
+  use http;
+
+  // A stand-in of the Rust Playground.
+  let handler = |req: server::Request, res: server::Response| {
+    let (req, _) = http::Request::read(req);
+    let json = match req.path.as_str() {
+      "/meta/versions" => r#"{"stable": {"rustc": {"version": "1.80.0"}}}"#.to_owned(),
+      "/execute" => {
+        let request: Value = serde_json::from_str(&req.body).unwrap();
+        assert_eq!(request["channel"], "nightly");
+        assert_eq!(request["edition"], "2018");
+        assert_eq!(request["mode"], "release");
+        // The output is the crate type, to be checked by the test.
+        format!(r#"{{"success": true, "exitDetail": "", "stdout": "{}\n",
+                    "stderr": "     Running `target/release/playground`\n"}}"#,
+                request["crateType"].as_str().unwrap())
+      }
+      "/meta/gist" => r#"{"id": "abc", "url": "https://gist.github.com/abc", "code": ""}"#.to_owned(),
+      _ => return http::send(res, StatusCode::NotFound, "not found"),
+    };
+    http::send(res, StatusCode::Ok, &json);
+  };
+  let mut listening = Server::http("127.0.0.1:0").unwrap().handle(handler).unwrap();
+  let playground = Playground::new(Some(format!("http://{}", listening.socket)));
+
+  let compilers = playground.get_compiler_info().unwrap();
+  assert_eq!((compilers[0].name.as_str(), compilers[0].version.as_str()), ("rust-stable", "1.80.0"));
+
+  let mut parameter = Parameter::new("fn main() {}", "rust-nightly");
+  parameter.options("edition2018,optimize");
+  parameter.save_permlink(true);
+  let response = playground.compile(parameter.clone()).unwrap();
+  assert_eq!(response.program_output, Some("bin\n".to_owned()));
+  assert_eq!(response.permlink, Some("abc".to_owned()));
+  let err = playground.get_permlink("abc").unwrap_err();
+  assert!(err.to_string().ends_with("/?gist=abc for the code"), "{}", err);
+
+  // The crate type is given by the options, or guessed from `fn main` outside comments.
+  parameter.save_permlink(false);
+  parameter.code = "// fn main() is not needed".to_owned();
+  assert_eq!(playground.compile(parameter.clone()).unwrap().program_output, Some("lib\n".to_owned()));
+  parameter.options("edition2018,optimize,bin");
+  assert_eq!(playground.compile(parameter.clone()).unwrap().program_output, Some("bin\n".to_owned()));
+  parameter.options("edition2018,optimize,bin,lib");
+  assert!(playground.compile(parameter.clone()).is_err());
+
+  parameter.compiler = "rust-unknown".to_owned();
+  assert!(playground.compile(parameter).is_err());
+  let _ = listening.close();
+}
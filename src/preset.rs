@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use serde_json;
+
+use config::CompilerAlias;
+use util;
+use wandbox::CompilerInfo;
+
+/// Named set of options and arguments, selected by `--preset`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Preset {
+  /// Name of the preset; in the configuration, the key is used instead.
+  #[serde(default)]
+  #[serde(skip_serializing)]
+  pub name: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub description: Option<String>,
+  /// Compilers to which the preset applies, e.g. `gcc` for `gcc-head` and `gcc-7.1.0`.
+  /// The preset applies to all compilers when this is empty.
+  #[serde(default)]
+  pub compilers: Vec<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub options: Option<String>,
+  #[serde(rename = "compile-args")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub compiler_args: Option<String>,
+  #[serde(rename = "runtime-args")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub runtime_args: Option<String>,
+}
+
+impl Preset {
+  pub fn applies_to(&self, compiler: &str) -> bool {
+    self.compilers.is_empty() ||
+    self.compilers.iter().any(|c| compiler == c || compiler.starts_with(&format!("{}-", c)))
+  }
+}
+
+/// Table of presets.  Several presets may share a name, for different compilers.
+#[derive(Debug, Clone)]
+pub struct Presets {
+  presets: Vec<Preset>,
+}
+
+impl Presets {
+  /// Returns the built-in presets, loaded from `presets.json`.
+  pub fn builtin() -> Presets {
+    let presets = serde_json::from_str(include_str!("presets.json")).expect("invalid presets.json");
+    Presets { presets: presets }
+  }
+
+  /// Returns the built-in presets, with the presets of the configuration taking precedence.
+  pub fn with_config(config: &HashMap<String, Preset>) -> Presets {
+    let mut names: Vec<_> = config.keys().collect();
+    names.sort();
+    let mut presets: Vec<_> = names.into_iter()
+                                   .map(|name| Preset { name: name.clone(), ..config[name].clone() })
+                                   .collect();
+    presets.extend(Self::builtin().presets);
+    Presets { presets: presets }
+  }
+
+  pub fn iter<'a>(&'a self) -> ::std::slice::Iter<'a, Preset> {
+    self.presets.iter()
+  }
+
+  /// Finds the presets of comma-separated names, for the compiler.
+  pub fn resolve(&self, names: &str, compiler: &str) -> ::Result<Vec<&Preset>> {
+    let mut presets = Vec::new();
+    for name in names.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+      let candidates: Vec<_> = self.presets.iter().filter(|p| p.name == name).collect();
+      if candidates.is_empty() {
+        let similar = util::suggest(name, self.presets.iter().map(|p| p.name.as_str())).map(str::to_owned);
+        bail!(::ErrorKind::UnknownPreset(name.to_owned(), similar));
+      }
+      match candidates.iter().find(|p| p.applies_to(compiler)) {
+        Some(preset) => presets.push(*preset),
+        None => {
+          bail!("preset `{}` is not available for `{}` (available for: {})",
+                name,
+                compiler,
+                util::str_join(candidates.iter().flat_map(|p| p.compilers.iter()), ", "))
+        }
+      }
+    }
+    Ok(presets)
+  }
+}
+
+/// Adds the arguments of the presets to the ones of a compiler alias.
+///
+/// The options are kept apart by `options`, since they override the ones of the alias.
+pub fn apply(alias: &mut CompilerAlias, presets: &[&Preset]) {
+  for preset in presets {
+    join(&mut alias.compiler_args, &preset.compiler_args, " ");
+    join(&mut alias.runtime_args, &preset.runtime_args, " ");
+  }
+}
+
+/// Returns the options of the presets, which conflict with each other only within a group.
+pub fn options(presets: &[&Preset]) -> Option<String> {
+  let mut options = None;
+  for preset in presets {
+    join(&mut options, &preset.options, ",");
+  }
+  options
+}
+
+/// Checks that the compiler accepts the raw arguments of the presets.
+pub fn check(presets: &[&Preset], info: &CompilerInfo) -> ::Result<()> {
+  for preset in presets {
+    if preset.compiler_args.is_some() && !info.compiler_option_raw {
+      bail!("preset `{}` needs raw compiler arguments, which `{}` does not accept",
+            preset.name,
+            info.name);
+    }
+    if preset.runtime_args.is_some() && !info.runtime_option_raw {
+      bail!("preset `{}` needs raw runtime arguments, which `{}` does not accept",
+            preset.name,
+            info.name);
+    }
+  }
+  Ok(())
+}
+
+fn join(base: &mut Option<String>, value: &Option<String>, sep: &str) {
+  if let Some(ref value) = *value {
+    *base = Some(match base.take() {
+                   Some(base) => format!("{}{}{}", base, sep, value),
+                   None => value.clone(),
+                 });
+  }
+}
+
+
+#[test]
+fn test_resolve_presets() {
+  let presets = Presets::builtin();
+  let resolved = presets.resolve("asan, strict", "clang-head").unwrap();
+  assert_eq!(resolved.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(), ["asan", "strict"]);
+
+  let resolved = presets.resolve("strict", "rust-1.18.0").unwrap();
+  assert_eq!(resolved[0].compiler_args, Some("-D warnings".to_owned()));
+
+  assert!(presets.resolve("asan", "rust-head").unwrap_err().to_string().contains("gcc, clang"));
+  assert!(presets.resolve("stric", "gcc-head").unwrap_err().to_string().contains("did you mean `strict`?"));
+  assert!(presets.resolve("asan", "gcc-head-c").is_ok());
+  assert!(presets.resolve("debug", "mono-head").is_err());
+}
+
+#[test]
+fn test_apply_presets() {
+  let mut config = HashMap::new();
+  config.insert("strict".to_owned(),
+                Preset {
+                  options: Some("warning".to_owned()),
+                  compiler_args: Some("-Werror".to_owned()),
+                  ..Preset::default()
+                });
+  let presets = Presets::with_config(&config);
+  let resolved = presets.resolve("strict,debug", "gcc-head").unwrap();
+
+  let mut alias = CompilerAlias {
+    compiler: "gcc-head".to_owned(),
+    options: Some("c++17".to_owned()),
+    ..CompilerAlias::default()
+  };
+  apply(&mut alias, &resolved);
+  assert_eq!(alias.options, Some("c++17".to_owned()));
+  assert_eq!(options(&resolved), Some("warning".to_owned()));
+  assert_eq!(alias.compiler_args, Some("-Werror -g -O0".to_owned()));
+
+  let src = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/list.json"));
+  let mut compilers: Vec<CompilerInfo> = serde_json::from_str(src).unwrap();
+  assert!(check(&resolved, &compilers[0]).is_ok());
+  compilers[0].compiler_option_raw = false;
+  assert!(check(&resolved, &compilers[0]).is_err());
+}
@@ -0,0 +1,401 @@
+//! A forward proxy of the Wandbox API, which caches deterministic results and limits the rate
+//! of requests from each client.
+//!
+//! Compile requests are cached by the normalized `Parameter`, except those saving a permlink.
+//! Only the results of the programs which exit with 0 are kept, since the others may be transient
+//! failures such as a time limit. The compiler list is cached for a while, and permlinks forever
+//! since they never change. A client bypasses the cache by `Cache-Control: no-cache`.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use hyper::method::Method;
+use hyper::server::{self, Handler, Listening};
+use hyper::status::StatusCode;
+use serde_json;
+
+use cassette::Cassette;
+use http;
+use util;
+use wandbox::{Parameter, Response, Wandbox};
+
+#[derive(Debug, Clone)]
+pub struct ProxyOptions {
+  /// Requests per minute forwarded for each client. Cached results are not counted.
+  pub rate_limit: u32,
+  /// How long the compiler list is cached.
+  pub list_ttl: Duration,
+  /// Number of results kept in memory, of which the oldest are dropped first.
+  pub cache_size: usize,
+  /// Directory to keep the cached results across restarts.
+  pub cache_dir: Option<Cassette>,
+}
+
+impl Default for ProxyOptions {
+  fn default() -> ProxyOptions {
+    ProxyOptions {
+      rate_limit: 60,
+      list_ttl: Duration::from_secs(3600),
+      cache_size: 1000,
+      cache_dir: None,
+    }
+  }
+}
+
+/// A response of the proxy.
+#[derive(Debug)]
+pub struct Reply {
+  pub status: StatusCode,
+  pub body: String,
+  /// Whether the result is served from the cache, if it is cacheable.
+  pub cached: Option<bool>,
+  /// Seconds until the client is allowed to send the next request, when it is rate limited.
+  pub retry_after: Option<u64>,
+}
+
+/// Results kept in memory, in the order of insertion.
+#[derive(Debug, Default)]
+struct Cache {
+  bodies: HashMap<String, String>,
+  keys: VecDeque<String>,
+}
+
+impl Cache {
+  fn get(&self, key: &str) -> Option<&String> {
+    self.bodies.get(key)
+  }
+
+  /// Inserts a result, and drops the oldest ones beyond the size.
+  fn insert(&mut self, key: &str, body: &str, size: usize) {
+    if self.bodies.insert(key.to_owned(), body.to_owned()).is_none() {
+      self.keys.push_back(key.to_owned());
+    }
+    while self.keys.len() > size {
+      if let Some(key) = self.keys.pop_front() {
+        self.bodies.remove(&key);
+      }
+    }
+  }
+}
+
+/// Tokens of a client, refilled at the rate limit.
+#[derive(Debug)]
+struct Bucket {
+  tokens: f64,
+  updated: Instant,
+}
+
+impl Bucket {
+  /// Returns the tokens refilled until now, up to the rate.
+  fn refilled(&self, rate: f64) -> f64 {
+    let elapsed = self.updated.elapsed();
+    let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+    (self.tokens + elapsed * rate / 60.0).min(rate)
+  }
+}
+
+pub struct Proxy {
+  upstream: Wandbox,
+  options: ProxyOptions,
+  list: Mutex<Option<(Instant, String)>>,
+  cache: Mutex<Cache>,
+  buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl Proxy {
+  pub fn new(upstream: Wandbox, options: ProxyOptions) -> Proxy {
+    Proxy {
+      upstream: upstream,
+      options: options,
+      list: Mutex::new(None),
+      cache: Mutex::new(Cache::default()),
+      buckets: Mutex::new(HashMap::new()),
+    }
+  }
+
+  /// Starts the proxy in background threads, and returns the handle which joins them when dropped.
+  pub fn start(self, addr: &str) -> ::Result<Listening> {
+    let listening = server::Server::http(addr)?.handle(self)?;
+    info!("proxy is listening on {}", listening.socket);
+    Ok(listening)
+  }
+
+  /// Answers a request from the client, from the cache or the upstream.
+  pub fn serve(&self, req: &http::Request) -> Reply {
+    let (client, method, path, body) = (req.client, &req.method, req.path.as_str(), req.body.as_str());
+    let key = match cache_key(method, path, body) {
+      Some(ref key) if !req.no_cache => key.clone(),
+      _ => return self.forward_limited(client, method, path, body, None),
+    };
+
+    if path == "/api/list.json" {
+      if let Some((ref time, ref list)) = *self.list.lock().unwrap() {
+        if time.elapsed() < self.options.list_ttl {
+          return reply(StatusCode::Ok, list.clone(), Some(true));
+        }
+      }
+    } else if let Some(body) = self.lookup(method, path, &key) {
+      return reply(StatusCode::Ok, body, Some(true));
+    }
+
+    let reply = self.forward_limited(client, method, path, body, Some(false));
+    if reply.status == StatusCode::Ok {
+      if path == "/api/list.json" {
+        *self.list.lock().unwrap() = Some((Instant::now(), reply.body.clone()));
+      } else if path != "/api/compile.json" || succeeded(&reply.body) {
+        self.store(method, path, &key, &reply.body);
+      }
+    }
+    reply
+  }
+
+  fn forward_limited(&self, client: IpAddr, method: &Method, path: &str, body: &str, cached: Option<bool>) -> Reply {
+    if let Err(wait) = self.take_token(client) {
+      return Reply {
+        status: StatusCode::TooManyRequests,
+        body: format!("rate limit of {} requests per minute is exceeded; retry after {} s",
+                      self.options.rate_limit,
+                      wait),
+        cached: cached,
+        retry_after: Some(wait),
+      };
+    }
+    let body = if *method == Method::Get { None } else { Some(body) };
+    match self.upstream.forward(method.clone(), path, body) {
+      Ok((status, body)) => reply(status, body, cached),
+      Err(err) => {
+        let message = util::str_join(err.iter().map(|e| e.to_string()), ": ");
+        warn!("{} {}: {}", method, path, message);
+        reply(StatusCode::BadGateway, message, cached)
+      }
+    }
+  }
+
+  /// Takes a token of the client, or returns the seconds until the next token.
+  fn take_token(&self, client: IpAddr) -> Result<(), u64> {
+    let rate = self.options.rate_limit as f64;
+    let mut buckets = self.buckets.lock().unwrap();
+    // A full bucket is the same as a new one, so they are dropped before adding a client.
+    if !buckets.contains_key(&client) {
+      buckets.retain(|_, bucket| bucket.refilled(rate) < rate);
+    }
+    let bucket = buckets.entry(client).or_insert_with(|| {
+                                                        Bucket {
+                                                          tokens: rate,
+                                                          updated: Instant::now(),
+                                                        }
+                                                      });
+    bucket.tokens = bucket.refilled(rate);
+    bucket.updated = Instant::now();
+    if bucket.tokens >= 1.0 {
+      bucket.tokens -= 1.0;
+      Ok(())
+    } else {
+      Err(((1.0 - bucket.tokens) * 60.0 / rate).ceil() as u64)
+    }
+  }
+
+  fn lookup(&self, method: &Method, path: &str, key: &str) -> Option<String> {
+    if let Some(body) = self.cache.lock().unwrap().get(key) {
+      return Some(body.clone());
+    }
+    let cassette = match self.options.cache_dir {
+      Some(ref cassette) => cassette,
+      None => return None,
+    };
+    match cassette.load(method.as_ref(), path, Some(key)) {
+      Ok((200, body)) => {
+        self.cache.lock().unwrap().insert(key, &body, self.options.cache_size);
+        Some(body)
+      }
+      _ => None,
+    }
+  }
+
+  fn store(&self, method: &Method, path: &str, key: &str, body: &str) {
+    self.cache.lock().unwrap().insert(key, body, self.options.cache_size);
+    if let Some(ref cassette) = self.options.cache_dir {
+      if let Err(err) = cassette.save(method.as_ref(), path, Some(key), 200, body) {
+        warn!("failed to save the cache: {}", err);
+      }
+    }
+  }
+}
+
+impl Handler for Proxy {
+  fn handle(&self, req: server::Request, mut res: server::Response) {
+    let start = Instant::now();
+    let (req, result) = http::Request::read(req);
+    let reply = match result {
+      Ok(()) => self.serve(&req),
+      Err((status, message)) => reply(status, message, None),
+    };
+    let elapsed = start.elapsed();
+    info!("{} {} {} -> {}{} ({} ms)",
+          req.client,
+          req.method,
+          req.path,
+          reply.status,
+          match reply.cached {
+            Some(true) => ", cache hit",
+            Some(false) => ", cache miss",
+            None => "",
+          },
+          elapsed.as_secs() * 1000 + (elapsed.subsec_nanos() / 1000000) as u64);
+
+    if let Some(wait) = reply.retry_after {
+      res.headers_mut().set_raw("Retry-After", vec![wait.to_string().into_bytes()]);
+    }
+    if let Some(cached) = reply.cached {
+      let value = if cached { "hit" } else { "miss" };
+      res.headers_mut().set_raw("X-Cache", vec![value.as_bytes().to_vec()]);
+    }
+    http::send(res, reply.status, &reply.body);
+  }
+}
+
+fn reply(status: StatusCode, body: String, cached: Option<bool>) -> Reply {
+  Reply {
+    status: status,
+    body: body,
+    cached: cached,
+    retry_after: None,
+  }
+}
+
+/// Returns the key of a cacheable request.
+///
+/// Compile requests are normalized by parsing and serializing the `Parameter` again, so that the
+/// formatting and the order of the keys do not matter.
+fn cache_key(method: &Method, path: &str, body: &str) -> Option<String> {
+  match (method, path) {
+    (&Method::Get, "/api/list.json") => Some(String::new()),
+    (&Method::Get, path) if path.starts_with("/api/permlink/") => Some(path.to_owned()),
+    (&Method::Post, "/api/compile.json") => {
+      let parameter: Parameter = match serde_json::from_str(body) {
+        Ok(parameter) => parameter,
+        Err(_) => return None,
+      };
+      if parameter.save == Some(true) {
+        return None;
+      }
+      serde_json::to_string(&parameter).ok()
+    }
+    _ => None,
+  }
+}
+
+/// Returns whether the result of a compile request is of a program which exited normally.
+fn succeeded(body: &str) -> bool {
+  match serde_json::from_str::<Response>(body) {
+    Ok(response) => response.status == 0 && response.signal.is_none(),
+    Err(_) => false,
+  }
+}
+
+
+#[test]
+fn test_cache_key() {
+  let a = cache_key(&Method::Post, "/api/compile.json", r#"{"code": "x", "compiler": "gcc-head"}"#);
+  let b = cache_key(&Method::Post, "/api/compile.json", r#"{ "compiler":"gcc-head","code":"x" }"#);
+  assert!(a.is_some());
+  assert_eq!(a, b);
+  assert_eq!(cache_key(&Method::Post, "/api/compile.json", r#"{"code": "x", "compiler": "gcc-head", "save": true}"#),
+             None);
+}
+
+#[test]
+fn test_cache() {
+  let mut cache = Cache::default();
+  cache.insert("a", "1", 2);
+  cache.insert("b", "2", 2);
+  cache.insert("a", "3", 2);
+  cache.insert("c", "4", 2);
+  assert_eq!((cache.get("a"), cache.get("b")), (None, Some(&"2".to_owned())));
+  assert_eq!(cache.get("c"), Some(&"4".to_owned()));
+
+  cache.insert("d", "5", 0);
+  assert!(cache.bodies.is_empty());
+}
+
+#[test]
+fn test_succeeded() {
+  assert!(succeeded(r#"{"status": 0, "program_message": "hello"}"#));
+  assert!(!succeeded(r#"{"status": 1}"#));
+  assert!(!succeeded(r#"{"status": 0, "signal": "Killed"}"#));
+  assert!(!succeeded("not found"));
+}
+
+#[test]
+fn test_proxy() {
+  use mock::{self, MockServer};
+
+  let mut server = MockServer::new(mock::default_list()).unwrap();
+  let abort = r#"{ "code": "abort", "response": { "status": 134, "signal": "Aborted" } }"#;
+  server.rule(serde_json::from_str(abort).unwrap()).unwrap();
+  server.rule(serde_json::from_str(r#"{ "response": { "status": 0, "program_message": "hello" } }"#).unwrap())
+        .unwrap();
+  let running = server.start("127.0.0.1:0").unwrap();
+  let options = ProxyOptions {
+    rate_limit: 3,
+    ..ProxyOptions::default()
+  };
+  let proxy = Proxy::new(Wandbox::new(Some(running.url())), options);
+  let request = |client: &str, method: Method, path: &str, body: &str| {
+    http::Request {
+      client: client.parse().unwrap(),
+      method: method,
+      path: path.to_owned(),
+      host: None,
+      no_cache: false,
+      body: body.to_owned(),
+    }
+  };
+
+  let body = r#"{"code": "int main() {}", "compiler": "gcc-head"}"#;
+  let reply = proxy.serve(&request("127.0.0.1", Method::Post, "/api/compile.json", body));
+  assert_eq!((reply.status, reply.cached), (StatusCode::Ok, Some(false)));
+  let reply = proxy.serve(&request("127.0.0.1", Method::Post, "/api/compile.json", body));
+  assert_eq!((reply.status, reply.cached), (StatusCode::Ok, Some(true)));
+  assert!(reply.body.contains("hello"));
+
+  let reply = proxy.serve(&request("127.0.0.1", Method::Get, "/api/list.json", ""));
+  assert_eq!((reply.status, reply.cached), (StatusCode::Ok, Some(false)));
+  let reply = proxy.serve(&request("127.0.0.1", Method::Get, "/api/list.json", ""));
+  assert_eq!(reply.cached, Some(true));
+
+  // A failed run is not cached, so the second one is forwarded again and rate limited.
+  let abort = r#"{"code": "abort();", "compiler": "gcc-head"}"#;
+  let reply = proxy.serve(&request("127.0.0.1", Method::Post, "/api/compile.json", abort));
+  assert_eq!((reply.status, reply.cached), (StatusCode::Ok, Some(false)));
+  assert!(reply.body.contains("Aborted"));
+  let reply = proxy.serve(&request("127.0.0.1", Method::Post, "/api/compile.json", abort));
+  assert_eq!(reply.status, StatusCode::TooManyRequests);
+
+  let mut no_cache = request("127.0.0.2", Method::Post, "/api/compile.json", body);
+  no_cache.no_cache = true;
+  let reply = proxy.serve(&no_cache);
+  assert_eq!((reply.status, reply.cached), (StatusCode::Ok, None));
+  assert_eq!(proxy.buckets.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn test_take_token() {
+  let options = ProxyOptions {
+    rate_limit: 60,
+    ..ProxyOptions::default()
+  };
+  let proxy = Proxy::new(Wandbox::new(None), options);
+  let clients: Vec<IpAddr> = vec!["127.0.0.1".parse().unwrap(), "127.0.0.2".parse().unwrap()];
+
+  assert!(proxy.take_token(clients[0]).is_ok());
+  // Refill the bucket of the first client, which is dropped when another client comes.
+  proxy.buckets.lock().unwrap().get_mut(&clients[0]).unwrap().tokens = 60.0;
+  assert!(proxy.take_token(clients[1]).is_ok());
+  assert_eq!(proxy.buckets.lock().unwrap().keys().collect::<Vec<_>>(), vec![&clients[1]]);
+
+  proxy.buckets.lock().unwrap().get_mut(&clients[1]).unwrap().tokens = 0.0;
+  assert_eq!(proxy.take_token(clients[1]), Err(1));
+}
@@ -0,0 +1,88 @@
+//! Rendering of errors for the users of the command.
+
+use std::env;
+use std::fmt::Write;
+
+use {Error, ErrorKind};
+
+/// Renders the error, its causes and a hint to fix it.
+///
+/// With `detail`, the debug form of each error and the backtrace (if captured) are appended.
+pub fn render(err: &Error, detail: bool) -> String {
+  let mut out = String::new();
+  writeln!(out, "error: {}", err).unwrap();
+  let mut last = err.to_string();
+  for cause in err.iter().skip(1) {
+    // Wrappers such as `hyper::Error::Io` repeat the message of their cause.
+    let message = cause.to_string();
+    if message != last {
+      writeln!(out, "  caused by: {}", message).unwrap();
+    }
+    last = message;
+  }
+  if let Some(hint) = hint(err) {
+    writeln!(out, "hint: {}", hint).unwrap();
+  }
+
+  if detail {
+    writeln!(out, "\ndetails:").unwrap();
+    for (i, cause) in err.iter().enumerate() {
+      writeln!(out, "  {}: {:?}", i, cause).unwrap();
+    }
+    match err.backtrace() {
+      Some(backtrace) => writeln!(out, "\nbacktrace:\n{:?}", backtrace).unwrap(),
+      None => writeln!(out, "\nbacktrace: not captured; set RUST_BACKTRACE=1 to capture it").unwrap(),
+    }
+  }
+  out
+}
+
+/// Returns whether the details of errors are requested by `RUST_BACKTRACE` or `WAN_BACKTRACE`.
+pub fn detail_requested() -> bool {
+  ["RUST_BACKTRACE", "WAN_BACKTRACE"].iter().any(|name| match env::var(name) {
+    Ok(value) => !value.is_empty() && value != "0",
+    Err(_) => false,
+  })
+}
+
+/// Finds a hint for the first error in the chain which has one.
+pub fn hint(err: &Error) -> Option<&'static str> {
+  let mut current = Some(err);
+  while let Some(err) = current {
+    if let Some(hint) = hint_of(err.kind()) {
+      return Some(hint);
+    }
+    // The causes chained by `chain_err` are boxed as `std::error::Error`.
+    current = err.1.next_error.as_ref().and_then(|next| next.downcast_ref::<Error>());
+  }
+  None
+}
+
+fn hint_of(kind: &ErrorKind) -> Option<&'static str> {
+  match *kind {
+    ErrorKind::UnknownCompiler(..) => Some("run `wan list` to see compiler names"),
+    ErrorKind::UnknownPreset(..) => Some("run `wan presets` to see the available presets"),
+    ErrorKind::InvalidConfig(..) => Some("run `wan config validate` to check the configuration files"),
+    ErrorKind::NoRecording(..) => Some("record the request by running without `--replay`"),
+    ErrorKind::Unreachable(..) |
+    ErrorKind::HttpStatus(..) => {
+      Some("run `wan ping` to check the endpoints, or choose another with `--url` or `--profile`")
+    }
+    _ => None,
+  }
+}
+
+
+#[test]
+fn test_render() {
+  use ResultExt;
+  let result: ::Result<()> = Err(ErrorKind::UnknownCompiler("gcc-hed".to_owned()).into());
+  let err = result.chain_err(|| "failed to compile main.cpp").unwrap_err();
+  assert_eq!(render(&err, false),
+             "error: failed to compile main.cpp\n  caused by: unknown compiler `gcc-hed`\n\
+              hint: run `wan list` to see compiler names\n");
+  assert!(render(&err, true).contains("\ndetails:\n  0: "));
+
+  let err: Error = "something failed".into();
+  assert_eq!(render(&err, false), "error: something failed\n");
+}
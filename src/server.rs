@@ -0,0 +1,240 @@
+//! A server with the HTTP API of Wandbox, which compiles the code by the local backend.
+//!
+//! Permlinks are saved as `<id>.json` in a directory, so that they survive restarts.
+//!
+//! `compile.ndjson` answers with the events of Wandbox, but it is not a stream: the local backend
+//! returns the outputs when the program finishes, so all events are sent at once after the run.
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use hyper::method::Method;
+use hyper::server::{self, Handler, Listening};
+use hyper::status::StatusCode;
+use serde_json;
+
+use backend::Backend;
+use http;
+use local::Local;
+use util;
+use wandbox::{Parameter, Response};
+use ResultExt;
+
+#[derive(Serialize, Deserialize)]
+struct Permlink {
+  parameter: Parameter,
+  result: Response,
+}
+
+/// An event of `compile.ndjson`.
+#[derive(Serialize)]
+struct Event<'a> {
+  #[serde(rename = "type")]
+  kind: &'a str,
+  data: &'a str,
+}
+
+pub struct Server {
+  local: Local,
+  permlink_dir: PathBuf,
+}
+
+impl Server {
+  pub fn new<P: Into<PathBuf>>(local: Local, permlink_dir: P) -> Server {
+    Server {
+      local: local,
+      permlink_dir: permlink_dir.into(),
+    }
+  }
+
+  /// Starts the server in background threads, and returns the handle which joins them when dropped.
+  pub fn start(self, addr: &str) -> ::Result<Listening> {
+    if self.local.compilers().is_empty() {
+      bail!("no compiler is configured in `local.compilers`");
+    }
+    fs::create_dir_all(&self.permlink_dir)
+      .chain_err(|| format!("failed to create {}", self.permlink_dir.display()))?;
+    let listening = server::Server::http(addr)?.handle(self)?;
+    info!("serving the Wandbox API on {}", listening.socket);
+    Ok(listening)
+  }
+
+  fn route(&self, method: &Method, path: &str, body: &str, host: Option<&str>) -> ::Result<(StatusCode, String)> {
+    Ok(match (method, path) {
+      (&Method::Get, "/api/list.json") => (StatusCode::Ok, serde_json::to_string(&self.local.compilers())?),
+      (&Method::Post, "/api/compile.json") => {
+        let response = self.compile(body, host)?;
+        (StatusCode::Ok, serde_json::to_string(&response)?)
+      }
+      (&Method::Post, "/api/compile.ndjson") => {
+        let response = self.compile(body, host)?;
+        (StatusCode::Ok, to_ndjson(&response)?)
+      }
+      // Without a web page, the URL of a permlink shows the same JSON as the API.
+      (&Method::Get, path) if path.starts_with("/api/permlink/") || path.starts_with("/permlink/") => {
+        let link = &path[path.rfind('/').unwrap() + 1..];
+        match self.load_permlink(link)? {
+          Some(json) => (StatusCode::Ok, json),
+          None => (StatusCode::NotFound, format!("permlink `{}` is not found", link)),
+        }
+      }
+      _ => (StatusCode::NotFound, "not found".to_owned()),
+    })
+  }
+
+  fn compile(&self, body: &str, host: Option<&str>) -> ::Result<Response> {
+    let mut parameter: Parameter = serde_json::from_str(body)
+      .chain_err(|| ::ErrorKind::InvalidParameter("invalid request".to_owned()))?;
+    let save = parameter.save == Some(true);
+    let mut response = self.local.compile(Parameter { save: None, ..parameter.clone() })?;
+    if save {
+      parameter.created_at = Some(util::format_time(SystemTime::now()));
+      let link = self.save_permlink(&parameter, &response)?;
+      response.url = host.map(|host| format!("http://{}/permlink/{}", host, link));
+      response.permlink = Some(link);
+    }
+    Ok(response)
+  }
+
+  fn save_permlink(&self, parameter: &Parameter, response: &Response) -> ::Result<String> {
+    let json = serde_json::to_string(&Permlink {
+                                        parameter: parameter.clone(),
+                                        result: response.clone(),
+                                      })?;
+    let link = format!("{:016x}", util::fnv1a(&[json.as_str(), parameter.created_at.as_ref().unwrap()]));
+    let path = self.permlink_dir.join(format!("{}.json", link));
+    File::create(&path)
+      .and_then(|mut f| f.write_all(json.as_bytes()))
+      .chain_err(|| format!("failed to write {}", path.display()))?;
+    info!("saved permlink {}", link);
+    Ok(link)
+  }
+
+  fn load_permlink(&self, link: &str) -> ::Result<Option<String>> {
+    if link.is_empty() || !link.chars().all(|c| c.is_digit(16)) {
+      return Ok(None);
+    }
+    let path = self.permlink_dir.join(format!("{}.json", link));
+    if !path.is_file() {
+      return Ok(None);
+    }
+    let mut json = String::new();
+    File::open(&path)
+      .and_then(|mut f| f.read_to_string(&mut json))
+      .chain_err(|| format!("failed to read {}", path.display()))?;
+    Ok(Some(json))
+  }
+}
+
+impl Handler for Server {
+  fn handle(&self, req: server::Request, res: server::Response) {
+    let (req, result) = http::Request::read(req);
+    let (status, content) = match result.map(|()| self.route(&req.method, &req.path, &req.body, req.host())) {
+      Ok(Ok(response)) => response,
+      Ok(Err(err)) => {
+        let message = util::str_join(err.iter().map(|e| e.to_string()), ": ");
+        warn!("{} {}: {}", req.method, req.path, message);
+        (status_of(&err), message)
+      }
+      Err(error) => error,
+    };
+    info!("{} {} -> {}", req.method, req.path, status);
+    http::send(res, status, &content);
+  }
+}
+
+/// Returns the status of an error: the faults of the request are the client's, and the others the server's.
+fn status_of(err: &::Error) -> StatusCode {
+  match *err.kind() {
+    ::ErrorKind::InvalidParameter(_) |
+    ::ErrorKind::UnknownCompiler(_) => StatusCode::BadRequest,
+    _ => StatusCode::InternalServerError,
+  }
+}
+
+/// Converts the response into the events of `compile.ndjson`, one JSON object per line.
+fn to_ndjson(response: &Response) -> ::Result<String> {
+  let status = response.status.to_string();
+  let mut events = vec![("Control", "Start")];
+  let outputs = [("CompilerMessageS", &response.compiler_output),
+                 ("CompilerMessageE", &response.compiler_error),
+                 ("StdOut", &response.program_output),
+                 ("StdErr", &response.program_error)];
+  for &(kind, output) in &outputs {
+    if let Some(ref data) = *output {
+      if !data.is_empty() {
+        events.push((kind, data.as_str()));
+      }
+    }
+  }
+  if let Some(ref signal) = response.signal {
+    events.push(("Signal", signal.as_str()));
+  }
+  events.push(("ExitCode", &status));
+  events.push(("Control", "Finish"));
+
+  let mut ndjson = String::new();
+  for (kind, data) in events {
+    ndjson.push_str(&serde_json::to_string(&Event {
+                                              kind: kind,
+                                              data: data,
+                                            })?);
+    ndjson.push('\n');
+  }
+  Ok(ndjson)
+}
+
+
+#[cfg(unix)]
+#[test]
+fn test_server() {
+  use wandbox::Wandbox;
+
+  let config = serde_json::from_str(r#"{
+    "timeout": 1,
+    "compilers": {
+      "bash-local": {"language": "Bash script", "source": "prog.sh", "run": "sh prog.sh"}
+    }
+  }"#).unwrap();
+  let dir = util::TempDir::new().unwrap();
+  let server = Server::new(Local::new(config), dir.path());
+  let mut listening = server.start("127.0.0.1:0").unwrap();
+  let wandbox = Wandbox::new(Some(format!("http://{}", listening.socket)));
+
+  assert_eq!(wandbox.get_compiler_info().unwrap()[0].name, "bash-local");
+
+  let mut parameter = Parameter::new("echo hello", "bash-local");
+  parameter.save_permlink(true);
+  let response = wandbox.submit(parameter).unwrap();
+  assert_eq!(response.program_output, Some("hello\n".to_owned()));
+  let permlink: Permlink = serde_json::from_str(&wandbox.get_permlink(&response.permlink.unwrap()).unwrap()).unwrap();
+  assert_eq!(permlink.parameter.code, "echo hello");
+  assert!(permlink.parameter.created_at.is_some());
+
+  assert!(wandbox.submit(Parameter::new("", "gcc-head")).is_err());
+
+  let status = |method, path, body| wandbox.forward(method, path, body).unwrap().0;
+  assert_eq!(status(Method::Post, "/api/compile.json", Some("{")), StatusCode::BadRequest);
+  let unknown = serde_json::to_string(&Parameter::new("", "gcc-head")).unwrap();
+  assert_eq!(status(Method::Post, "/api/compile.json", Some(&unknown)), StatusCode::BadRequest);
+  assert_eq!(status(Method::Get, "/api/permlink/0123456789abcdef", None), StatusCode::NotFound);
+  // The permlink cannot be saved without the directory.
+  fs::remove_dir_all(dir.path()).unwrap();
+  let mut parameter = Parameter::new("echo hello", "bash-local");
+  parameter.save_permlink(true);
+  let json = serde_json::to_string(&parameter).unwrap();
+  assert_eq!(status(Method::Post, "/api/compile.json", Some(&json)), StatusCode::InternalServerError);
+  let _ = listening.close();
+}
+
+#[test]
+fn test_to_ndjson() {
+  let response: Response = serde_json::from_str(r#"{"status": 0, "program_output": "hi\n"}"#).unwrap();
+  assert_eq!(to_ndjson(&response).unwrap(),
+             "{\"type\":\"Control\",\"data\":\"Start\"}\n\
+              {\"type\":\"StdOut\",\"data\":\"hi\\n\"}\n\
+              {\"type\":\"ExitCode\",\"data\":\"0\"}\n\
+              {\"type\":\"Control\",\"data\":\"Finish\"}\n");
+}
@@ -1,9 +1,17 @@
 use serde;
-use serde_json;
-use std::io::Write;
+use serde_json::{self, Value};
+use strsim;
+use toml;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use ResultExt;
 
-#[derive(Debug, Serialize, Deserialize)]
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Either<L, R> {
   Left(L),
@@ -42,7 +50,214 @@ pub fn str_join<I, S>(iter: I, join: &str) -> String
   })
 }
 
+/// Quotes a string for POSIX shells if it contains any special characters.
+pub fn shell_quote(s: &str) -> String {
+  let is_plain = |c: char| c.is_alphanumeric() || "-_=+.,/:@%^".contains(c);
+  if !s.is_empty() && s.chars().all(is_plain) {
+    s.to_owned()
+  } else {
+    format!("'{}'", s.replace("'", r"'\''"))
+  }
+}
+
+pub fn shell_join<I, S>(iter: I) -> String
+  where I: IntoIterator<Item = S>,
+        S: AsRef<str>
+{
+  str_join(iter.into_iter().map(|s| shell_quote(s.as_ref())), " ")
+}
+
+/// Splits the raw arguments of a `Parameter`, which are given one per line.
+pub fn raw_args(raw: &Option<String>) -> Vec<String> {
+  match *raw {
+    Some(ref raw) if !raw.is_empty() => raw.split('\n').map(ToOwned::to_owned).collect(),
+    _ => Vec::new(),
+  }
+}
+
+/// Returns the candidate most similar to `name`, if any is similar enough.
+pub fn suggest<'a, I>(name: &str, candidates: I) -> Option<&'a str>
+  where I: IntoIterator<Item = &'a str>
+{
+  let mut best = None;
+  for candidate in candidates {
+    let score = strsim::jaro_winkler(name, candidate);
+    if score > 0.8 && best.map_or(true, |(s, _)| score > s) {
+      best = Some((score, candidate));
+    }
+  }
+  best.map(|(_, candidate)| candidate)
+}
+
+/// Formats a time in ISO 8601, e.g. `2017-04-01T12:34:56.789Z`.
+pub fn format_time(time: SystemTime) -> String {
+  let d = time.duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0));
+  let secs = d.as_secs();
+  let (hour, min, sec) = (secs / 3600 % 24, secs / 60 % 60, secs % 60);
+
+  // Converts the days since the epoch to a civil date (Howard Hinnant's algorithm).
+  let z = (secs / 86400) as i64 + 719468;
+  let era = z / 146097;
+  let doe = z - era * 146097;
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+  format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+          year,
+          month,
+          day,
+          hour,
+          min,
+          sec,
+          d.subsec_nanos() / 1000000)
+}
+
+/// Returns the 64-bit FNV-1a hash of the strings, each of which is terminated by a NUL.
+pub fn fnv1a<S: AsRef<str>>(parts: &[S]) -> u64 {
+  let mut hash: u64 = 0xcbf29ce484222325;
+  for part in parts {
+    for &b in part.as_ref().as_bytes().iter().chain(&[0]) {
+      hash ^= b as u64;
+      hash = hash.wrapping_mul(0x100000001b3);
+    }
+  }
+  hash
+}
+
+/// A temporary directory, removed when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+  pub fn new() -> ::Result<TempDir> {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+    let mut attempt = 0;
+    loop {
+      let path = env::temp_dir().join(format!("wan-{}-{}", nanos, attempt));
+      match fs::create_dir(&path) {
+        Ok(()) => return Ok(TempDir(path)),
+        Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => attempt += 1,
+        Err(err) => return Err(err).chain_err(|| format!("failed to create {}", path.display())),
+      }
+    }
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.0
+  }
+}
+
+impl Drop for TempDir {
+  fn drop(&mut self) {
+    let _ = fs::remove_dir_all(&self.0);
+  }
+}
+
+/// Parses a TOML document into the JSON value of the same structure.
+///
+/// Datetimes are kept as strings, since JSON has no such type.
+pub fn parse_toml(text: &str) -> ::Result<Value> {
+  let mut parser = toml::Parser::new(text);
+  match parser.parse() {
+    Some(table) => Ok(toml_to_json(toml::Value::Table(table))),
+    None => {
+      let messages = parser.errors.iter().map(|err| {
+        let (line, col) = parser.to_linecol(err.lo);
+        format!("{} at line {} column {}", err.desc, line + 1, col + 1)
+      });
+      bail!(str_join(messages, "; "))
+    }
+  }
+}
+
+fn toml_to_json(value: toml::Value) -> Value {
+  match value {
+    toml::Value::String(s) |
+    toml::Value::Datetime(s) => Value::String(s),
+    toml::Value::Integer(i) => Value::from(i),
+    toml::Value::Float(f) => Value::from(f),
+    toml::Value::Boolean(b) => Value::Bool(b),
+    toml::Value::Array(values) => Value::Array(values.into_iter().map(toml_to_json).collect()),
+    toml::Value::Table(table) => Value::Object(table.into_iter().map(|(k, v)| (k, toml_to_json(v))).collect()),
+  }
+}
+
+/// Writes a JSON value, which must be an object, as a TOML document.
+pub fn to_toml(value: &Value) -> ::Result<String> {
+  match json_to_toml(value)? {
+    table @ toml::Value::Table(_) => Ok(table.to_string()),
+    _ => bail!("TOML document must be a table"),
+  }
+}
+
+fn json_to_toml(value: &Value) -> ::Result<toml::Value> {
+  Ok(match *value {
+       Value::Null => bail!("null cannot be written in TOML"),
+       Value::Bool(b) => toml::Value::Boolean(b),
+       Value::Number(ref n) => {
+         match n.as_i64() {
+           Some(i) => toml::Value::Integer(i),
+           None => toml::Value::Float(n.as_f64().unwrap_or_default()),
+         }
+       }
+       Value::String(ref s) => toml::Value::String(s.clone()),
+       Value::Array(ref values) => toml::Value::Array(values.iter().map(json_to_toml).collect::<::Result<_>>()?),
+       Value::Object(ref map) => {
+         let mut table = toml::Table::new();
+         for (key, value) in map {
+           table.insert(key.clone(), json_to_toml(value)?);
+         }
+         toml::Value::Table(table)
+       }
+     })
+}
+
 pub fn dump_to_json<S: serde::Serialize>(value: &S) -> ::Result<()> {
   ::std::io::stdout().write_all(serde_json::to_string_pretty(value)?.as_bytes())?;
   Ok(())
 }
+
+
+#[test]
+fn test_suggest() {
+  assert_eq!(suggest("languges", vec!["url", "languages", "options"]), Some("languages"));
+  assert_eq!(suggest("foo", vec!["url", "languages", "options"]), None);
+}
+
+#[test]
+fn test_parse_toml() {
+  let value = parse_toml(r#"
+default = "main"
+[targets.main]
+main = "main.cpp"
+files = ["*.h"]
+timeout = 1.5
+"#)
+    .unwrap();
+  assert_eq!(value["default"], "main");
+  assert_eq!(value["targets"]["main"]["files"][0], "*.h");
+  assert_eq!(value["targets"]["main"]["timeout"], 1.5);
+
+  let err = parse_toml("[targets\nmain = 1").unwrap_err().to_string();
+  assert!(err.contains("line 1"), "{}", err);
+
+  assert_eq!(parse_toml(&to_toml(&value).unwrap()).unwrap(), value);
+  assert!(to_toml(&Value::Null).is_err());
+}
+
+#[test]
+fn test_fnv1a() {
+  assert_eq!(fnv1a::<&str>(&[]), 0xcbf29ce484222325);
+  assert_eq!(fnv1a(&["a"]), 0x089be207b544f1e4);
+  assert!(fnv1a(&["a", "b"]) != fnv1a(&["ab"]));
+}
+
+#[test]
+fn test_format_time() {
+  assert_eq!(format_time(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+  assert_eq!(format_time(UNIX_EPOCH + Duration::new(951825845, 123000000)),
+             "2000-02-29T12:04:05.123Z");
+}
@@ -1,105 +1,125 @@
-use std::io::Read;
-use std::path::Path;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-use hyper;
-use hyper_native_tls;
+use hyper::method::Method;
+use hyper::status::StatusCode;
 use serde_json;
 
+use cassette::Cassette;
+use config::Config;
+use http;
 use util::{self, Either};
+use ResultExt;
 
-const WANDBOX_URL: &'static str = "https://wandbox.org";
+pub const WANDBOX_URL: &'static str = "https://wandbox.org";
 
 pub struct Wandbox {
-  url: String,
+  http: http::Client,
 }
 
 impl Wandbox {
   pub fn new(url: Option<String>) -> Wandbox {
     let url = url.unwrap_or(WANDBOX_URL.into());
-    Wandbox { url: url }
+    Self::with_endpoints(vec![url])
   }
 
-  pub fn compile(&self, param: Parameter, verbose: bool) -> ::Result<Response> {
-    if verbose {
-      println!("[HTTP session]");
-    }
+  /// Creates a client which fails over to the next endpoint when one is not available.
+  pub fn with_endpoints(endpoints: Vec<String>) -> Wandbox {
+    Wandbox { http: http::Client::new(endpoints) }
+  }
 
-    let run_url = format!("{}/api/compile.json", self.url);
+  /// Creates a client with the endpoints, TLS and authentication settings of the configuration.
+  pub fn from_config(config: &Config) -> Wandbox {
+    Wandbox { http: http::Client::from_config(config, WANDBOX_URL) }
+  }
 
-    // create HTTP client.
-    let tls = hyper_native_tls::NativeTlsClient::new()?;
-    let connector = hyper::net::HttpsConnector::new(tls);
-    let client = hyper::Client::with_connector(connector);
+  /// Records all requests and responses to a HAR file.
+  pub fn trace_http<P: Into<PathBuf>>(&mut self, path: P) {
+    self.http.trace_http(path);
+  }
 
-    if verbose {
-      println!("HTTP POST {}", run_url);
-      println!("{}", serde_json::to_string_pretty(&param)?);
-    }
+  /// Records the responses to a cassette, or replays them from it without sending requests.
+  pub fn cassette(&mut self, cassette: Cassette) {
+    self.http.cassette(cassette);
+  }
 
-    let mut res = client.post(&run_url)
-                        .header(hyper::header::ContentType::json())
-                        .body(&serde_json::to_string(&param)?)
-                        .send()?;
+  pub fn endpoints(&self) -> &[String] {
+    self.http.endpoints()
+  }
 
-    if verbose {
-      println!("HTTP STATUS: {}", res.status);
-    }
+  /// Returns the URL of the endpoint in use.
+  pub fn url(&self) -> &str {
+    self.http.url()
+  }
+
+  /// Sends a request to the API and returns the status and the body, e.g. to relay them.
+  pub fn forward(&self, method: Method, path: &str, body: Option<&str>) -> ::Result<(StatusCode, String)> {
+    self.http.forward(method, path, body)
+  }
+
+  fn get(&self, path: &str) -> ::Result<String> {
+    self.forward(Method::Get, path, None).map(|(_, body)| body)
+  }
 
-    let mut buf = String::new();
-    res.read_to_string(&mut buf)?;
-    if verbose {
-      println!("HTTP RESPONSE:");
-      println!("{}", buf);
-      println!();
+  /// Measures the time to fetch the compiler list from an endpoint.
+  pub fn ping(&self, endpoint: &str) -> ::Result<(Duration, usize)> {
+    let start = Instant::now();
+    let (status, buf) = self.http.get_from(endpoint, "/api/list.json")?;
+    let elapsed = start.elapsed();
+    if !status.is_success() {
+      bail!("responded with {}", status);
     }
+    let compilers: Vec<CompilerInfo> = serde_json::from_str(&buf)?;
+    Ok((elapsed, compilers.len()))
+  }
 
-    let response = serde_json::from_str(&buf)?;
-    Ok(response)
+  /// Sends a compile request.
+  ///
+  /// `verbose` is ignored; the request and the response are logged at the debug and trace levels
+  /// instead of being printed.
+  #[deprecated(since = "0.0.3", note = "use `submit`, and enable the logs to see the HTTP session")]
+  pub fn compile(&self, param: Parameter, _verbose: bool) -> ::Result<Response> {
+    self.submit(param)
   }
 
-  pub fn get_compiler_info_raw(&self) -> ::Result<Box<Read>> {
-    let list_url = format!("{}/api/list.json", self.url);
+  /// Sends a compile request, whose body and response are logged at the trace level.
+  pub fn submit(&self, param: Parameter) -> ::Result<Response> {
+    let path = "/api/compile.json";
+    let run_url = format!("{}{}", self.url(), path);
+    let (status, buf) = self.forward(Method::Post, path, Some(&serde_json::to_string(&param)?))?;
 
-    // create HTTP client.
-    let tls = hyper_native_tls::NativeTlsClient::new()?;
-    let connector = hyper::net::HttpsConnector::new(tls);
-    let client = hyper::Client::with_connector(connector);
+    if !status.is_success() {
+      bail!(::ErrorKind::HttpStatus(run_url, status.to_string()));
+    }
+    let response = serde_json::from_str(&buf).chain_err(|| format!("invalid response from {}", run_url))?;
+    Ok(response)
+  }
 
-    let res = client.get(&list_url).send()?;
-    Ok(Box::new(res))
+  pub fn get_compiler_info_raw(&self) -> ::Result<Box<Read>> {
+    let body = self.get("/api/list.json")?;
+    Ok(Box::new(io::Cursor::new(body.into_bytes())))
   }
 
   pub fn get_compiler_info(&self) -> ::Result<Vec<CompilerInfo>> {
     let res = self.get_compiler_info_raw()?;
-    let res = serde_json::from_reader(res)?;
+    let res = serde_json::from_reader(res).chain_err(|| format!("invalid compiler list from {}", self.url()))?;
     Ok(res)
   }
 
   pub fn get_permlink(&self, link: &str) -> ::Result<String> {
-    let permlink_url = format!("{}/api/permlink/{}", self.url, link);
-
-    // create HTTP client.
-    let tls = hyper_native_tls::NativeTlsClient::new()?;
-    let connector = hyper::net::HttpsConnector::new(tls);
-    let client = hyper::Client::with_connector(connector);
-
-    let mut res = client.get(&permlink_url).send()?;
-
-    let mut buf = String::new();
-    res.read_to_string(&mut buf)?;
-
-    Ok(buf)
+    self.get(&format!("/api/permlink/{}", link))
   }
 
   pub fn permlink_url(&self, link: &str) -> String {
-    format!("{}/permlink/{}", self.url, link)
+    format!("{}/permlink/{}", self.url(), link)
   }
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Code {
-  file: String,
-  code: String,
+  pub file: String,
+  pub code: String,
 }
 
 impl Code {
@@ -123,7 +143,7 @@ impl Code {
 }
 
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Parameter {
   pub code: String,
   pub compiler: String,
@@ -155,10 +175,11 @@ pub struct Parameter {
 
 impl Parameter {
   pub fn new<S1: Into<String>, S2: Into<String>>(code: S1, compiler: S2) -> Self {
-    let mut ret = Self::default();
-    ret.code = code.into();
-    ret.compiler = compiler.into();
-    ret
+    Parameter {
+      code: code.into(),
+      compiler: compiler.into(),
+      ..Self::default()
+    }
   }
 
   pub fn options<S: Into<String>>(&mut self, options: S) -> &mut Self {
@@ -214,7 +235,7 @@ impl Parameter {
 }
 
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Response {
   pub status: i32,
   pub signal: Option<String>,
@@ -229,7 +250,7 @@ pub struct Response {
 }
 
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompilerInfo {
   pub name: String,
   pub version: String,
@@ -256,7 +277,7 @@ impl ::std::fmt::Display for CompilerInfo {
   }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct CompilerSwitch {
   pub default: bool,
   pub name: String,
@@ -266,13 +287,13 @@ pub struct CompilerSwitch {
   pub display_flags: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct CompilerSwitchMultiOptions {
   pub default: String,
   pub options: Vec<CompilerOption>,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct CompilerOption {
   pub name: String,
   #[serde(rename = "display-name")]
@@ -299,7 +320,7 @@ fn test_compiler_switch() {
   let dst = serde_json::from_str::<Either<CompilerSwitch, CompilerSwitchMultiOptions>>(src).unwrap();
 
   let dst = dst.into_left().expect("invalid type");
-  assert_eq!(dst.default, true);
+  assert!(dst.default);
   assert_eq!(dst.name, "sprout");
   assert_eq!(dst.display_name, "Sprout");
   assert_eq!(dst.display_flags, "-I/usr/local/sprout");